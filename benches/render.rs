@@ -0,0 +1,51 @@
+//! Benchmarks of every renderer on tracks of increasing length. Run with `cargo bench`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use sax_fingering_chart::{layer, pdf::{write_pdf, PdfTrack}, Config, Notes, Size, Song};
+
+/// Numbers of notes in the benchmarked tracks
+const LENGTHS: [usize; 3] = [64, 256, 1024];
+
+/// Notes the benchmarked tracks cycle through, within the range of the default config
+const PITCHES: [&str; 8] = ["Bb3", "C4", "D4", "Eb4", "F4", "G4", "A4", "Bb4"];
+
+/// Loads a single-track song of the given number of notes, with a bar line after every 4 notes
+fn song(length: usize, notes: &Notes, config: &Config) -> Song
+{
+    let text: Vec<&str> = (0..length)
+        .flat_map(|i| if i % 4 == 3 { vec![PITCHES[i % PITCHES.len()], "|"] } else { vec![PITCHES[i % PITCHES.len()]] })
+        .collect();
+    let path = std::env::temp_dir().join(format!("sax_fingering_chart_bench_{}.txt", length));
+    std::fs::write(&path, text.join(" ")).expect("Failed to write note list");
+    Song::load(path.to_str().expect("Temp path is not valid unicode"), notes, config).expect("Failed to load note list")
+}
+
+fn renderers(c: &mut Criterion)
+{
+    let mut config = layer::load(None).expect("Failed to load config");
+    config.show_title = true;
+    let notes = Notes::new(&config).expect("Failed to load notes");
+    let songs: Vec<(usize, Song)> = LENGTHS.iter().map(|&length| (length, song(length, &notes, &config))).collect();
+    let pdf_path = std::env::temp_dir().join("sax_fingering_chart_bench.pdf");
+    let pdf_path = pdf_path.to_str().expect("Temp path is not valid unicode");
+
+    let mut group = c.benchmark_group("render");
+    for (length, song) in &songs
+    {
+        let track = song.tracks().next().expect("Song has no tracks");
+        group.bench_with_input(BenchmarkId::new("cell_images", length), track, |b, track| b.iter(|| track.cell_images(&config)));
+        group.bench_with_input(BenchmarkId::new("row_images", length), track, |b, track| b.iter(|| track.row_images(&config, Size::Scale(1))));
+        group.bench_with_input(BenchmarkId::new("track_image", length), track, |b, track| b.iter(|| track.track_image(&config, Size::Scale(1))));
+        group.bench_with_input(BenchmarkId::new("track_image_scaled", length), track, |b, track| b.iter(|| track.track_image(&config, Size::Scale(4))));
+        group.bench_with_input(BenchmarkId::new("track_svg", length), track, |b, track| b.iter(|| track.track_svg(&config)));
+        group.bench_with_input(BenchmarkId::new("pdf", length), track, |b, track| b.iter(||
+        {
+            let pdf_track = PdfTrack { name: track.name.clone(), rows: track.row_images(&config, Size::Scale(1)) };
+            write_pdf(pdf_path, "Benchmark", &[pdf_track], config.spacing, &config.pdf).expect("Failed to write pdf");
+        }));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, renderers);
+criterion_main!(benches);
@@ -39,7 +39,7 @@ fn build()
 
     let const_decs = load_images!
     {
-        SEPARATOR, A, B, C, D, E, F, G, FLAT, FRONT_KEY, FRONT_KEY_OFF,
+        SEPARATOR, A, B, C, D, E, F, G, FLAT, SHARP, FRONT_KEY, FRONT_KEY_OFF,
         FRONT_F_KEY, FRONT_F_KEY_OFF,PALM_KEY, PALM_KEY_OFF, OCTAVE_KEY, 
         OCTAVE_KEY_OFF, BIS_KEY, BIS_KEY_OFF, LOW_A_KEY, LOW_A_KEY_OFF, 
         SIDE_KEY, SIDE_KEY_OFF, HIGH_F_SHARP_KEY, HIGH_F_SHARP_KEY_OFF, 
@@ -51,10 +51,10 @@ fn build()
     std::fs::write(&dest_path, const_decs).unwrap();
 }
 
-fn load_image<'a>(name: &str) -> Vec<u8>
+fn load_image(name: &str) -> Vec<u8>
 {
     let root = std::env::var("CARGO_MANIFEST_DIR").unwrap();
     std::fs::read(Path::new(&root).join("image_data").join(name))
-        .expect(&format!("Failed to read image file: {}", name))
+        .unwrap_or_else(|_| panic!("Failed to read image file: {}", name))
 }
 
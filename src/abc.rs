@@ -0,0 +1,939 @@
+use crate::{Error, note::{Spelling, LETTERS, SHARP_ORDER}, song::{Metadata, RawNote, RawSong, score_channel}};
+use std::{fs, collections::{HashMap, HashSet}, convert::TryFrom};
+
+/// Ticks per beat used for notes read from ABC, which divides evenly by the tuplets most tunes use
+const TICKS_PER_BEAT: u32 = 960;
+/// Ticks in a whole note, which ABC note lengths are a fraction of
+const WHOLE_NOTE: u32 = TICKS_PER_BEAT * 4;
+/// The most `>` or `<` a broken rhythm may be written with
+const MAX_BROKEN: u32 = 3;
+/// The most semitones a voice may be transposed by in either direction
+const MAX_TRANSPOSE: i16 = 127;
+/// The most bars a multi-bar rest may last
+const MAX_REST_BARS: u32 = 1000;
+/// Velocity of notes played before any dynamics are given
+const DEFAULT_VELOCITY: u8 = 80;
+/// Dynamics decorations and the velocity each one is played at
+const DYNAMICS: [(&str, u8); 10] =
+[
+    ("pppp", 16), ("ppp", 24), ("pp", 36), ("p", 48), ("mp", 64), ("mf", 80), ("f", 96), ("ff", 108), ("fff", 118), ("ffff", 127)
+];
+/// How many fifths each mode's key signature is from the major key on the same tonic. Modes are matched on their
+/// first three letters.
+const MODES: [(&str, i16); 9] = [("maj", 0), ("ion", 0), ("mix", -1), ("dor", -2), ("min", -3), ("aeo", -3), ("phr", -4), ("lyd", 1), ("loc", -5)];
+
+/// A note as written, part of a chord when several start together
+struct Pitch
+{
+    key: i16,
+    spelling: Option<Spelling>,
+    ticks: u32,
+    /// Whether the note is tied to the same note in the chord which follows
+    tie: bool
+}
+
+/// Something written in the body of a tune, in the order it was written
+enum Item
+{
+    /// Notes starting together and how far they move the voice on. Rests have no notes.
+    Notes(Vec<Pitch>, u32),
+    /// A bar line, which may end or start a repeated section. Double bars end sections without repeating them.
+    Bar { end_repeat: bool, start_repeat: bool, double: bool },
+    /// The start of an ending, which is only played on the listed ranges of passes through a repeated section
+    Ending(Vec<(u32, u32)>),
+    /// A change in dynamics
+    Velocity(u8)
+}
+
+/// A voice of a tune along with the state of the parser within that voice
+struct Voice
+{
+    id: String,
+    name: Option<String>,
+    /// Semitones from written to concert pitch
+    transpose: i16,
+    /// The alteration of each letter in the key signature, starting from C
+    key: [i16; 7],
+    /// The length of a note without a length given, as a fraction of a whole note
+    unit: (u32, u32),
+    items: Vec<Item>,
+    /// Accidentals written so far in the current bar, by letter and octave
+    accidentals: HashMap<(usize, i16), i16>,
+    /// Factor the next note's length is scaled by, after a broken rhythm
+    broken: Option<(u32, u32)>,
+    /// Factor the lengths of notes in a tuplet are scaled by, along with how many notes of the tuplet remain
+    tuplet: Option<((u32, u32), u32)>
+}
+
+/// A tune being read, made up of its header fields and voices
+struct Tune
+{
+    metadata: Metadata,
+    key: [i16; 7],
+    unit: Option<(u32, u32)>,
+    meter: Option<(u32, u32)>,
+    voices: Vec<Voice>,
+    current: Option<usize>
+}
+
+/// Read the first tune of an ABC file. Each voice becomes a track, and repeated sections are played out in full.
+/// Pitches are converted from written to concert pitch using the transposition of each voice, then moved by the
+/// given number of semitones.
+pub(crate) fn read(path: &str, transposition: i16) -> Result<RawSong, Error>
+{
+    let text = fs::read_to_string(path)?;
+    let mut song = parse(&text, transposition)?;
+    let tunes = text.lines().filter(|line| line.starts_with("X:")).count();
+    if tunes > 1
+    {
+        song.warnings.push(format!("{}: reading the first of {} tunes", path, tunes));
+    }
+    Ok(song)
+}
+
+/// Read the first tune of an ABC document. The header runs up to the `K:` field, and the tune ends at the first
+/// blank line after it.
+pub(crate) fn parse(text: &str, transposition: i16) -> Result<RawSong, Error>
+{
+    let mut tune = Tune
+    {
+        metadata: Metadata::default(),
+        key: [0; 7],
+        unit: None,
+        meter: Some((4, 4)),
+        voices: Vec::new(),
+        current: None
+    };
+    let mut in_tune = false;
+    let mut in_body = false;
+    for line in text.lines()
+    {
+        let line = line.split('%').next().unwrap_or_default();
+        let trimmed = line.trim();
+        if trimmed.is_empty()
+        {
+            if in_body
+            {
+                break;
+            }
+            continue;
+        }
+        if let Some((field, value)) = field_line(trimmed, in_body)
+        {
+            if field == 'X'
+            {
+                if in_tune
+                {
+                    break;
+                }
+                in_tune = true;
+                continue;
+            }
+            tune.field(field, value, in_body)?;
+            if field == 'K' && !in_body
+            {
+                in_body = true;
+                tune.start_body();
+            }
+        }
+        else if in_body
+        {
+            tune.read_body(line)?;
+        }
+    }
+    if !in_body
+    {
+        return Err(Error::Score(String::from("tune has no key (K:) field")));
+    }
+    tune.finish(transposition)
+}
+
+/// Split a field line such as `T:Title` into its letter and value. Lines in the body which start with a note
+/// followed by a repeat mark are notes rather than fields.
+fn field_line(line: &str, in_body: bool) -> Option<(char, &str)>
+{
+    let mut chars = line.chars();
+    let letter = chars.next()?;
+    if !letter.is_ascii_alphabetic() || ('a'..='g').contains(&letter) || chars.next()? != ':'
+    {
+        return None;
+    }
+    let value = &line[2..];
+    if in_body && ('A'..='G').contains(&letter) && value.contains('|')
+    {
+        return None;
+    }
+    Some((letter, value.trim()))
+}
+
+impl Tune
+{
+    /// Apply a field from the header or body of a tune
+    fn field(&mut self, field: char, value: &str, in_body: bool) -> Result<(), Error>
+    {
+        match field
+        {
+            'T' if !in_body && self.metadata.title.is_none() => self.metadata.title = Some(value.to_string()),
+            'C' if !in_body => self.metadata.text.push(value.to_string()),
+            'L' =>
+            {
+                let unit = fraction(value).ok_or_else(|| Error::Score(format!("invalid unit note length '{}'", value)))?;
+                if in_body
+                {
+                    self.voice().unit = unit;
+                }
+                else
+                {
+                    self.unit = Some(unit);
+                }
+            },
+            'M' => self.meter = meter(value)?,
+            'K' =>
+            {
+                let key = key_signature(value)?;
+                if in_body
+                {
+                    self.voice().key = key;
+                }
+                else
+                {
+                    self.key = key;
+                }
+            },
+            'V' =>
+            {
+                let mut words = split_words(value).into_iter();
+                let id = words.next().unwrap_or_default();
+                let index = self.voice_index(&id);
+                let voice = &mut self.voices[index];
+                for word in words
+                {
+                    match word.split_once('=')
+                    {
+                        Some(("name", name)) | Some(("nm", name)) => voice.name = Some(name.to_string()),
+                        Some(("transpose", semitones)) | Some(("t", semitones)) =>
+                        {
+                            voice.transpose = match semitones.parse::<i64>()
+                            {
+                                Ok(semitones) if (-MAX_TRANSPOSE as i64..=MAX_TRANSPOSE as i64).contains(&semitones) => semitones as i16,
+                                Ok(_) => return Err(Error::Score(format!("voice transposition {} is too large", semitones))),
+                                Err(_) => 0
+                            };
+                        },
+                        _ => ()
+                    }
+                }
+                if in_body
+                {
+                    self.current = Some(index);
+                }
+            },
+            _ => ()
+        }
+        Ok(())
+    }
+
+    /// Settle the unit note length once the header has been read, and give voices declared in the header its key
+    fn start_body(&mut self)
+    {
+        let unit = match (self.unit, self.meter)
+        {
+            (Some(unit), _) => unit,
+            (None, Some((beats, beat_type))) if beats * 4 < beat_type * 3 => (1, 16),
+            _ => (1, 8)
+        };
+        self.unit = Some(unit);
+        for voice in self.voices.iter_mut()
+        {
+            voice.key = self.key;
+            voice.unit = unit;
+        }
+    }
+
+    /// Find a voice by its id, adding it if it hasn't been seen yet
+    fn voice_index(&mut self, id: &str) -> usize
+    {
+        match self.voices.iter().position(|voice| voice.id == id)
+        {
+            Some(index) => index,
+            None =>
+            {
+                self.voices.push(Voice
+                {
+                    id: id.to_string(),
+                    name: None,
+                    transpose: 0,
+                    key: self.key,
+                    unit: self.unit.unwrap_or((1, 8)),
+                    items: Vec::new(),
+                    accidentals: HashMap::new(),
+                    broken: None,
+                    tuplet: None
+                });
+                self.voices.len() - 1
+            }
+        }
+    }
+
+    /// The voice being written to. Music before any voice is named goes to the first voice.
+    fn voice(&mut self) -> &mut Voice
+    {
+        let index = match self.current
+        {
+            Some(index) => index,
+            None => if self.voices.is_empty() { self.voice_index("") } else { 0 }
+        };
+        self.current = Some(index);
+        &mut self.voices[index]
+    }
+
+    /// The length of a bar in ticks, or of a whole note without a meter
+    fn bar_ticks(&self) -> Result<u32, Error>
+    {
+        scale_ticks(WHOLE_NOTE, self.meter.unwrap_or((1, 1)))
+    }
+
+    /// Read a line of music. Chord symbols, annotations, grace notes, slurs and decorations other than dynamics are
+    /// skipped.
+    fn read_body(&mut self, line: &str) -> Result<(), Error>
+    {
+        let chars: Vec<char> = line.chars().collect();
+        let at = |i: usize| chars.get(i).copied().unwrap_or_default();
+        let mut i = 0;
+        while i < chars.len()
+        {
+            let c = chars[i];
+            match c
+            {
+                '"' | '{' =>
+                {
+                    let close = if c == '"' { '"' } else { '}' };
+                    i = skip_past(&chars, i + 1, close);
+                },
+                '!' | '+' =>
+                {
+                    let end = skip_past(&chars, i + 1, c);
+                    let name: String = chars[i + 1..end.saturating_sub(1).max(i + 1)].iter().collect();
+                    if let Some(&(_, velocity)) = DYNAMICS.iter().find(|&&(dynamic, _)| dynamic == name)
+                    {
+                        self.voice().items.push(Item::Velocity(velocity));
+                    }
+                    i = end;
+                },
+                '[' if at(i + 1).is_ascii_alphabetic() && at(i + 2) == ':' =>
+                {
+                    let end = skip_past(&chars, i, ']');
+                    let value: String = chars[i + 3..end.saturating_sub(1).max(i + 3)].iter().collect();
+                    self.field(at(i + 1), value.trim(), true)?;
+                    i = end;
+                },
+                '[' if at(i + 1).is_ascii_digit() => i = self.read_ending(&chars, i + 1),
+                '|' | ':' | '[' if c != '[' || at(i + 1) == '|' => i = self.read_bar(&chars, i),
+                '[' =>
+                {
+                    // Chords last as long as their first note, a length after the chord scales every note in it
+                    let mut pitches = Vec::new();
+                    i += 1;
+                    while i < chars.len() && chars[i] != ']'
+                    {
+                        match self.read_pitch(&chars, &mut i)?
+                        {
+                            Some(pitch) => pitches.push(pitch),
+                            None => i += 1
+                        }
+                    }
+                    i += 1;
+                    let (numerator, denominator) = read_length(&chars, &mut i);
+                    let tie = at(i) == '-';
+                    if tie
+                    {
+                        i += 1;
+                    }
+                    for pitch in pitches.iter_mut()
+                    {
+                        pitch.ticks = scale_ticks(pitch.ticks, (numerator, denominator))?;
+                        pitch.tie |= tie;
+                    }
+                    if let Some(advance) = pitches.first().map(|pitch| pitch.ticks)
+                    {
+                        self.voice().push_notes(pitches, advance)?;
+                    }
+                },
+                '(' if at(i + 1).is_ascii_digit() => i = self.read_tuplet(&chars, i + 1),
+                '>' | '<' =>
+                {
+                    let mut count = 0;
+                    while at(i) == c
+                    {
+                        count += 1;
+                        i += 1;
+                    }
+                    if count > MAX_BROKEN
+                    {
+                        return Err(Error::Score(format!("broken rhythm '{}' is longer than {} marks", c.to_string().repeat(count as usize), MAX_BROKEN)));
+                    }
+                    let (longer, shorter) = ((1 << (count + 1)) - 1, 1);
+                    let (before, after) = if c == '>' { (longer, shorter) } else { (shorter, longer) };
+                    let voice = self.voice();
+                    if let Some(Item::Notes(pitches, advance)) = voice.items.iter_mut().rev().find(|item| matches!(item, Item::Notes(..)))
+                    {
+                        for pitch in pitches.iter_mut()
+                        {
+                            pitch.ticks = scale_ticks(pitch.ticks, (before, 1 << count))?;
+                        }
+                        *advance = scale_ticks(*advance, (before, 1 << count))?;
+                    }
+                    voice.broken = Some((after, 1 << count));
+                },
+                '-' =>
+                {
+                    if let Some(Item::Notes(pitches, _)) = self.voice().items.last_mut()
+                    {
+                        pitches.iter_mut().for_each(|pitch| pitch.tie = true);
+                    }
+                    i += 1;
+                },
+                '^' | '_' | '=' | 'A'..='G' | 'a'..='g' => match self.read_pitch(&chars, &mut i)?
+                {
+                    Some(pitch) =>
+                    {
+                        let advance = pitch.ticks;
+                        self.voice().push_notes(vec![pitch], advance)?;
+                    },
+                    None => i += 1
+                },
+                'z' | 'x' =>
+                {
+                    i += 1;
+                    let voice = self.voice();
+                    let (numerator, denominator) = read_length(&chars, &mut i);
+                    let ticks = note_ticks(voice.unit, numerator, denominator)?;
+                    voice.push_notes(Vec::new(), ticks)?;
+                },
+                'Z' | 'X' =>
+                {
+                    // Multi-bar rests, with a bar line between each bar
+                    i += 1;
+                    let digits = i;
+                    let bars = read_number(&chars, &mut i).unwrap_or(if i == digits { 1 } else { u32::MAX });
+                    let ticks = self.bar_ticks()?;
+                    if bars > MAX_REST_BARS || bars.checked_mul(ticks).is_none()
+                    {
+                        let bars: String = chars[digits..i].iter().collect();
+                        return Err(Error::Score(format!("multi-bar rest of {} bars is too long", bars)));
+                    }
+                    let voice = self.voice();
+                    for bar in 0..bars
+                    {
+                        if bar > 0
+                        {
+                            voice.items.push(Item::Bar { end_repeat: false, start_repeat: false, double: false });
+                        }
+                        voice.items.push(Item::Notes(Vec::new(), ticks));
+                    }
+                },
+                _ => i += 1
+            }
+        }
+        Ok(())
+    }
+
+    /// Read a bar line starting at the given position, along with an ending written straight after it. Returns the
+    /// position after the bar line.
+    fn read_bar(&mut self, chars: &[char], mut i: usize) -> usize
+    {
+        let mut token = String::new();
+        while let Some(&c) = chars.get(i)
+        {
+            let is_bar = match c
+            {
+                '|' | ':' => true,
+                '[' => chars.get(i + 1) == Some(&'|'),
+                ']' => token.ends_with('|'),
+                _ => false
+            };
+            if !is_bar
+            {
+                break;
+            }
+            token.push(c);
+            i += 1;
+        }
+        if !token.contains('|') && token != "::"
+        {
+            return i;
+        }
+        let voice = self.voice();
+        voice.accidentals.clear();
+        voice.items.push(Item::Bar
+        {
+            end_repeat: token.starts_with(':'),
+            start_repeat: token.ends_with(':'),
+            double: token.contains("||") || token.contains('[') || token.contains(']')
+        });
+        match chars.get(i)
+        {
+            Some(c) if c.is_ascii_digit() => self.read_ending(chars, i),
+            Some('[') if chars.get(i + 1).is_some_and(char::is_ascii_digit) => self.read_ending(chars, i + 1),
+            _ => i
+        }
+    }
+
+    /// Read the passes an ending is played on, such as `1`, `1,3` or `1-2`. Returns the position after the list.
+    fn read_ending(&mut self, chars: &[char], mut i: usize) -> usize
+    {
+        let mut passes = Vec::new();
+        while let Some(first) = read_number(chars, &mut i)
+        {
+            let last = if chars.get(i) == Some(&'-')
+            {
+                i += 1;
+                read_number(chars, &mut i).unwrap_or(first)
+            }
+            else
+            {
+                first
+            };
+            passes.push((first, last));
+            if chars.get(i) != Some(&',')
+            {
+                break;
+            }
+            i += 1;
+        }
+        self.voice().items.push(Item::Ending(passes));
+        i
+    }
+
+    /// Read a tuplet such as `(3` or `(3:2:3`, starting after the bracket. Returns the position after the tuplet.
+    fn read_tuplet(&mut self, chars: &[char], mut i: usize) -> usize
+    {
+        let notes = read_number(chars, &mut i).unwrap_or(3).max(1);
+        let field = |i: &mut usize|
+        {
+            if chars.get(*i) == Some(&':')
+            {
+                *i += 1;
+                read_number(chars, i)
+            }
+            else
+            {
+                None
+            }
+        };
+        let time = field(&mut i);
+        let count = field(&mut i).unwrap_or(notes);
+        let compound = self.meter.is_some_and(|(beats, _)| beats % 3 == 0 && beats > 3);
+        let time = time.unwrap_or(match notes
+        {
+            3 | 6 => 2,
+            2 | 4 | 8 => 3,
+            _ => if compound { 3 } else { 2 }
+        });
+        self.voice().tuplet = Some(((time, notes), count));
+        i
+    }
+
+    /// Read a note with its accidental, octave marks, length and tie. Accidentals carry on for the rest of the bar,
+    /// otherwise the key signature applies. Returns None if there is no note at the given position, or an error if the
+    /// note is too long or out of range.
+    fn read_pitch(&mut self, chars: &[char], i: &mut usize) -> Result<Option<Pitch>, Error>
+    {
+        let voice = self.voice();
+        let mut alter: Option<i16> = None;
+        while let Some(&c) = chars.get(*i)
+        {
+            match c
+            {
+                '^' => alter = Some(alter.unwrap_or(0).saturating_add(1)),
+                '_' => alter = Some(alter.unwrap_or(0).saturating_sub(1)),
+                '=' => alter = Some(0),
+                _ => break
+            }
+            *i += 1;
+        }
+        let (letter, index) = match chars.get(*i).and_then(|&c| LETTERS.iter().position(|&l| l == c.to_ascii_uppercase()).map(|index| (c, index)))
+        {
+            Some(found) => found,
+            None => return Ok(None)
+        };
+        *i += 1;
+        let mut octave: i16 = if letter.is_ascii_uppercase() { 4 } else { 5 };
+        while let Some(&c) = chars.get(*i)
+        {
+            match c
+            {
+                '\'' => octave = octave.saturating_add(1),
+                ',' => octave = octave.saturating_sub(1),
+                _ => break
+            }
+            *i += 1;
+        }
+        if !(-1..=9).contains(&octave)
+        {
+            return Err(Error::Score(format!("invalid pitch octave {}", octave)));
+        }
+        if alter.is_some_and(|alter| !(-2..=2).contains(&alter))
+        {
+            return Err(Error::Score(String::from("invalid pitch with more than a double sharp or flat")));
+        }
+        let alter = match alter
+        {
+            Some(alter) =>
+            {
+                voice.accidentals.insert((index, octave), alter);
+                alter
+            },
+            None => voice.accidentals.get(&(index, octave)).copied().unwrap_or(voice.key[index])
+        };
+        let key = match Spelling::natural(letter)
+        {
+            Some(natural) => (octave + 1) * 12 + natural + alter,
+            None => return Ok(None)
+        };
+        let (numerator, denominator) = read_length(chars, i);
+        let tie = chars.get(*i) == Some(&'-');
+        if tie
+        {
+            *i += 1;
+        }
+        let ticks = note_ticks(voice.unit, numerator, denominator)?;
+        Ok(Some(Pitch { key, spelling: Spelling::with_letter(key, letter), ticks, tie }))
+    }
+
+    /// Turn every voice into a track, taking the bar lines from the first voice. A tune with a single unnamed voice
+    /// is named after its title.
+    fn finish(self, transposition: i16) -> Result<RawSong, Error>
+    {
+        let single = self.voices.len() <= 1;
+        let title = self.metadata.title.clone();
+        let mut song = RawSong { ticks_per_beat: TICKS_PER_BEAT, bars: vec![0], metadata: self.metadata, tracks: Vec::new(), names: Vec::new(), key_signatures: Vec::new(), warnings: Vec::new() };
+        for (i, voice) in self.voices.into_iter().enumerate()
+        {
+            let (notes, bars) = voice.play(score_channel(i), transposition)?;
+            if i == 0
+            {
+                song.bars = bars;
+            }
+            let name = voice.name.clone().or_else(|| if single
+            {
+                title.clone()
+            }
+            else
+            {
+                Some(format!("Voice {}", voice.id)).filter(|_| !voice.id.is_empty())
+            });
+            song.tracks.push(notes);
+            song.names.push((name, None));
+        }
+        Ok(song)
+    }
+}
+
+impl Voice
+{
+    /// Add notes or a rest, scaling their length by any broken rhythm or tuplet they are part of
+    fn push_notes(&mut self, mut pitches: Vec<Pitch>, mut advance: u32) -> Result<(), Error>
+    {
+        let mut factors = Vec::with_capacity(2);
+        factors.extend(self.broken.take());
+        if let Some((factor, remaining)) = self.tuplet
+        {
+            factors.push(factor);
+            self.tuplet = if remaining > 1 { Some((factor, remaining - 1)) } else { None };
+        }
+        for factor in factors
+        {
+            for pitch in pitches.iter_mut()
+            {
+                pitch.ticks = scale_ticks(pitch.ticks, factor)?;
+            }
+            advance = scale_ticks(advance, factor)?;
+        }
+        self.items.push(Item::Notes(pitches, advance));
+        Ok(())
+    }
+
+    /// Play through the voice, following repeats and endings, and return its notes along with the tick each of its
+    /// bars starts on. Repeats go back to the last start repeat, double bar or repeat played, or to the start. Fails if
+    /// a note is transposed out of range.
+    fn play(&self, channel: u8, transposition: i16) -> Result<(Vec<RawNote>, Vec<u32>), Error>
+    {
+        let mut notes: Vec<RawNote> = Vec::new();
+        let mut bars = vec![0];
+        // Notes waiting for the note they are tied to, keyed by concert pitch
+        let mut ties: HashMap<i16, usize> = HashMap::new();
+        let mut repeated: HashSet<usize> = HashSet::new();
+        let mut velocity = DEFAULT_VELOCITY;
+        let mut tick: u32 = 0;
+        let mut section_start = 0;
+        let mut pass = 1;
+        let mut skipping = false;
+        let mut i = 0;
+        while let Some(item) = self.items.get(i)
+        {
+            i += 1;
+            match *item
+            {
+                Item::Notes(ref pitches, advance) if !skipping =>
+                {
+                    let mut tied = HashMap::new();
+                    for pitch in pitches
+                    {
+                        let out_of_range = || Error::Score(format!("pitch {} is transposed out of range", pitch.key));
+                        let concert = pitch.key.checked_add(self.transpose).ok_or_else(out_of_range)?;
+                        let index = match ties.get(&concert)
+                        {
+                            Some(&index) =>
+                            {
+                                notes[index].duration = tick.saturating_add(pitch.ticks) - notes[index].start;
+                                index
+                            },
+                            None =>
+                            {
+                                let key = concert.checked_add(transposition).ok_or_else(out_of_range)?;
+                                let spelling = pitch.spelling.and_then(|spelling| spelling.transposed(pitch.key, key));
+                                notes.push(RawNote { key, velocity, channel, program: None, start: tick, duration: pitch.ticks, spelling, fingering: None });
+                                notes.len() - 1
+                            }
+                        };
+                        if pitch.tie
+                        {
+                            tied.insert(concert, index);
+                        }
+                    }
+                    ties = tied;
+                    tick = tick.saturating_add(advance);
+                },
+                Item::Notes(..) => (),
+                Item::Velocity(value) => velocity = value,
+                Item::Ending(ref passes) => skipping = !passes.iter().any(|&(first, last)| (first..=last).contains(&pass)),
+                Item::Bar { end_repeat, start_repeat, double } =>
+                {
+                    if !skipping && bars.last() != Some(&tick)
+                    {
+                        bars.push(tick);
+                    }
+                    if end_repeat && !skipping && repeated.insert(i)
+                    {
+                        i = section_start;
+                        pass = 2;
+                        continue;
+                    }
+                    if end_repeat || start_repeat || double
+                    {
+                        skipping = false;
+                        section_start = i;
+                        if !end_repeat
+                        {
+                            pass = 1;
+                        }
+                    }
+                }
+            }
+        }
+        Ok((notes, bars))
+    }
+}
+
+/// The ticks in a note of the given length, in multiples of the unit note length
+fn note_ticks(unit: (u32, u32), numerator: u32, denominator: u32) -> Result<u32, Error>
+{
+    let ticks = WHOLE_NOTE as u64 * unit.0 as u64 * numerator as u64 / (unit.1 as u64 * denominator as u64);
+    u32::try_from(ticks).map_err(|_| Error::Score(format!("note length {}/{} is too long", numerator, denominator)))
+}
+
+/// Scale a length in ticks by a fraction, failing if the result is too long to count in ticks
+fn scale_ticks(ticks: u32, (numerator, denominator): (u32, u32)) -> Result<u32, Error>
+{
+    ticks.checked_mul(numerator)
+        .map(|ticks| ticks / denominator)
+        .ok_or_else(|| Error::Score(format!("note length {}/{} is too long", numerator, denominator)))
+}
+
+/// Read a note length such as `2`, `3/2`, `/` or `//`. A slash without a number halves the length.
+fn read_length(chars: &[char], i: &mut usize) -> (u32, u32)
+{
+    let numerator = read_number(chars, i).unwrap_or(1);
+    let mut denominator = 1;
+    while chars.get(*i) == Some(&'/')
+    {
+        *i += 1;
+        denominator = read_number(chars, i).unwrap_or(2).saturating_mul(denominator);
+    }
+    (numerator.max(1), denominator.max(1))
+}
+
+/// Read a whole number, if there is one at the given position
+fn read_number(chars: &[char], i: &mut usize) -> Option<u32>
+{
+    let start = *i;
+    while chars.get(*i).is_some_and(char::is_ascii_digit)
+    {
+        *i += 1;
+    }
+    chars[start..*i].iter().collect::<String>().parse().ok()
+}
+
+/// The position after the next instance of a character, or the end of the line if there isn't one
+fn skip_past(chars: &[char], start: usize, close: char) -> usize
+{
+    chars.iter().skip(start).position(|&c| c == close).map_or(chars.len(), |offset| start + offset + 1)
+}
+
+/// Read a fraction such as `1/8`
+fn fraction(value: &str) -> Option<(u32, u32)>
+{
+    let (numerator, denominator) = value.split_once('/').unwrap_or((value, "1"));
+    let fraction = (numerator.trim().parse().ok()?, denominator.trim().parse().ok()?);
+    if fraction.0 == 0 || fraction.1 == 0 { None } else { Some(fraction) }
+}
+
+/// Read a meter, where `C` is common time and `C|` is cut time. Additive meters such as `2+3/8` are summed. Free
+/// meter has no bar length. Meters whose bars are too long to count in ticks, or whose beats are shorter than a tick,
+/// are an error.
+fn meter(value: &str) -> Result<Option<(u32, u32)>, Error>
+{
+    let (beats, beat_type) = match value
+    {
+        "C" => return Ok(Some((4, 4))),
+        "C|" => return Ok(Some((2, 2))),
+        _ => match value.split_once('/')
+        {
+            Some(meter) => meter,
+            None => return Ok(None)
+        }
+    };
+    let invalid = || Error::Score(format!("meter {} doesn't fit in ticks", value));
+    let mut total: u32 = 0;
+    for beats in beats.split('+')
+    {
+        match beats.trim().parse::<u32>()
+        {
+            Ok(beats) => total = total.checked_add(beats).ok_or_else(invalid)?,
+            Err(_) => return Ok(None)
+        }
+    }
+    let beat_type: u32 = match beat_type.trim().parse()
+    {
+        Ok(beat_type) => beat_type,
+        Err(_) => return Ok(None)
+    };
+    if total == 0 || beat_type == 0
+    {
+        return Ok(None);
+    }
+    if beat_type > WHOLE_NOTE || total.checked_mul(WHOLE_NOTE).is_none()
+    {
+        return Err(invalid());
+    }
+    Ok(Some((total, beat_type)))
+}
+
+/// Read a key such as `G`, `F#m`, `Bb dor` or `D exp ^f ^c`, returning the alteration of each letter starting from
+/// C. Accidentals written after the key change the key signature, or make up all of it after `exp`.
+fn key_signature(value: &str) -> Result<[i16; 7], Error>
+{
+    let mut key = [0; 7];
+    let mut words = split_words(value).into_iter().peekable();
+    let tonic = words.peek().cloned().unwrap_or_default();
+    let mut chars = tonic.chars();
+    match chars.next()
+    {
+        Some(letter @ 'A'..='G') =>
+        {
+            words.next();
+            let mut fifths = SHARP_ORDER.iter().position(|&sharp| sharp == letter).unwrap_or_default() as i16 - 1;
+            let mut mode = chars.as_str();
+            if let Some(rest) = mode.strip_prefix('#')
+            {
+                fifths += 7;
+                mode = rest;
+            }
+            else if let Some(rest) = mode.strip_prefix('b')
+            {
+                fifths -= 7;
+                mode = rest;
+            }
+            let mut mode = mode.to_lowercase();
+            if mode.is_empty() && words.peek().is_some_and(|word| is_mode(word))
+            {
+                mode = words.next().unwrap_or_default().to_lowercase();
+            }
+            if mode == "exp"
+            {
+                fifths = 0;
+            }
+            else if mode == "m"
+            {
+                fifths -= 3;
+            }
+            else if let Some(&(_, offset)) = MODES.iter().find(|&&(name, _)| mode.get(..3) == Some(name))
+            {
+                fifths += offset;
+            }
+            else if !mode.is_empty()
+            {
+                return Err(Error::Score(format!("invalid key '{}'", value)));
+            }
+            for i in 0..fifths.unsigned_abs().min(7) as usize
+            {
+                let (letter, alter) = if fifths > 0 { (SHARP_ORDER[i], 1) } else { (SHARP_ORDER[6 - i], -1) };
+                key[LETTERS.iter().position(|&l| l == letter).unwrap_or_default()] = alter;
+            }
+        },
+        // Highland bagpipe music is written with the key of D but the signature left out, or with no key at all
+        _ if tonic == "Hp" => key = [1, 0, 0, 1, 0, 0, 0],
+        _ => ()
+    }
+    for word in words
+    {
+        let alter = match word.chars().next()
+        {
+            Some('^') => 1,
+            Some('_') => -1,
+            Some('=') => 0,
+            _ => continue
+        };
+        if let Some(index) = word.chars().last().and_then(|letter| LETTERS.iter().position(|&l| l == letter.to_ascii_uppercase()))
+        {
+            key[index] = alter;
+        }
+    }
+    Ok(key)
+}
+
+/// Whether a word of a key field names a mode
+fn is_mode(word: &str) -> bool
+{
+    let word = word.to_lowercase();
+    word == "m" || word == "exp" || MODES.iter().any(|&(name, _)| word.get(..3) == Some(name))
+}
+
+/// Split a field value into words at whitespace, keeping quoted text together without its quotes
+fn split_words(value: &str) -> Vec<String>
+{
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut quoted = false;
+    for c in value.chars()
+    {
+        match c
+        {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted =>
+            {
+                if !word.is_empty()
+                {
+                    words.push(std::mem::take(&mut word));
+                }
+            },
+            c => word.push(c)
+        }
+    }
+    if !word.is_empty()
+    {
+        words.push(word);
+    }
+    words
+}
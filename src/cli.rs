@@ -0,0 +1,153 @@
+use crate::{Config, OutputFormat, FileType, KeySignatures, Size, Sizes, TranspositionType, exercise::Exercise};
+use clap::Parser;
+
+/// Takes midi, MusicXML, ABC or note list files and outputs fingering charts for a saxophone, or generates scale exercises and fingering references. Options given here override cfg.ron.
+#[derive(Parser)]
+#[command(version, about)]
+pub struct Args
+{
+    /// Midi, MusicXML, ABC or note list files to generate charts for, or - to read a note list from standard input. The format is chosen by file extension
+    #[arg(required_unless_present_any = ["exercises", "reference"])]
+    pub files: Vec<String>,
+
+    /// Generate a scale or arpeggio exercise, such as "Bb major", "F# harmonic-minor thirds" or "D dorian arpeggio". May be given more than once
+    #[arg(short, long = "exercise", value_name = "EXERCISE")]
+    pub exercises: Vec<Exercise>,
+
+    /// Write a printable reference chart of every configured note and all of its fingerings
+    #[arg(long)]
+    pub reference: bool,
+
+    /// Path to a config file, which takes priority over every other config
+    #[arg(short, long)]
+    pub config: Option<String>,
+
+    /// Directory charts are written to
+    #[arg(short, long)]
+    output: Option<String>,
+
+    /// How charts are laid out into files
+    #[arg(short, long, value_enum, ignore_case = true)]
+    format: Option<OutputFormat>,
+
+    /// The type of file charts are written to
+    #[arg(long, value_enum, ignore_case = true)]
+    file_type: Option<FileType>,
+
+    /// The sax type, used for note transposition
+    #[arg(short, long, value_enum, ignore_case = true)]
+    transposition: Option<TranspositionType>,
+
+    /// The key note names are spelled in: auto, detect, or a concert key such as Eb or F#m
+    #[arg(short, long = "key", value_name = "KEY")]
+    key_signature: Option<KeySignatures>,
+
+    /// The visual spacing between consecutive notes
+    #[arg(long)]
+    spacing: Option<usize>,
+
+    /// The number of notes per row
+    #[arg(short, long, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    notes_per_row: Option<usize>,
+
+    /// The whole-number factor charts are scaled up by in every output format
+    #[arg(short, long, value_parser = clap::value_parser!(u32).range(1..), conflicts_with_all = ["width", "dpi"])]
+    scale: Option<u32>,
+
+    /// Scale charts up as far as possible without any file being wider than this many pixels
+    #[arg(long, value_name = "PIXELS", value_parser = clap::value_parser!(u32).range(1..), conflicts_with = "dpi")]
+    width: Option<u32>,
+
+    /// Scale charts to about this many pixels per inch when printed, where an unscaled chart pixel is a point
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    dpi: Option<u32>,
+
+    /// Only output these tracks, given as a comma separated list of track numbers in output order starting from 0
+    #[arg(long, value_delimiter = ',')]
+    tracks: Option<Vec<usize>>,
+
+    /// Only output tracks with names matching these patterns, given as a comma separated list. `*` matches anything.
+    #[arg(long, value_delimiter = ',')]
+    track_names: Option<Vec<String>>,
+
+    /// Only output tracks played on these midi channels (1-16), given as a comma separated list
+    #[arg(long, value_delimiter = ',', value_parser = clap::value_parser!(u8).range(1..=16))]
+    channels: Option<Vec<u8>>,
+
+    /// Output percussion tracks (channel 10) instead of skipping them
+    #[arg(long)]
+    include_percussion: bool,
+
+    /// Skip tracks with fewer notes than this
+    #[arg(long)]
+    min_notes: Option<usize>
+}
+
+impl Args
+{
+    /// Override config values with any options given on the command line
+    pub fn apply(&self, config: &mut Config)
+    {
+        if let Some(output) = &self.output
+        {
+            config.output_path = output.clone();
+        }
+        if let Some(format) = self.format
+        {
+            config.output_format = format;
+        }
+        if let Some(file_type) = self.file_type
+        {
+            config.file_type = file_type;
+        }
+        if let Some(transposition) = self.transposition
+        {
+            config.transposition_type = transposition;
+        }
+        if let Some(key_signature) = self.key_signature
+        {
+            config.key_signature = key_signature;
+        }
+        if let Some(spacing) = self.spacing
+        {
+            config.spacing = spacing;
+        }
+        if let Some(notes_per_row) = self.notes_per_row
+        {
+            config.notes_per_row = notes_per_row;
+        }
+        if let Some(scale) = self.scale
+        {
+            config.scale = scale;
+            config.sizes = Sizes::default();
+        }
+        if let Some(width) = self.width
+        {
+            config.sizes = Sizes::all(Size::Width(width));
+        }
+        if let Some(dpi) = self.dpi
+        {
+            config.sizes = Sizes::all(Size::Dpi(dpi));
+        }
+        if let Some(tracks) = &self.tracks
+        {
+            config.tracks = tracks.clone();
+        }
+        if let Some(track_names) = &self.track_names
+        {
+            config.track_names = track_names.clone();
+        }
+        if let Some(channels) = &self.channels
+        {
+            config.channels = channels.clone();
+        }
+        if self.include_percussion
+        {
+            config.skip_percussion = false;
+        }
+        if let Some(min_notes) = self.min_notes
+        {
+            config.min_notes = min_notes;
+        }
+    }
+}
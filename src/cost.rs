@@ -0,0 +1,71 @@
+use crate::{keys::Key, note::Fingering};
+use enumset::EnumSet;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// A group of keys played by the same finger. Moving that finger from one key in the group to another
+/// is penalized.
+#[derive(Deserialize)]
+pub struct FingerGroup
+{
+    keys: EnumSet<Key>,
+    penalty: f32
+}
+
+/// Weights used to score how awkward it is to move from one fingering to another
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct CostModel
+{
+    /// The weight of each key, keys which aren't listed have a weight of 1
+    key_weights: HashMap<Key, f32>,
+    /// Groups of keys played by a single finger
+    fingers: Vec<FingerGroup>,
+    /// Subtracted from the cost of moving to a fingering marked as preferred
+    preferred_bonus: f32
+}
+
+impl Default for CostModel
+{
+    fn default() -> Self
+    {
+        CostModel { key_weights: HashMap::new(), fingers: Vec::new(), preferred_bonus: 0.0 }
+    }
+}
+
+impl CostModel
+{
+    /// The cost of moving from one fingering to another. Keys which change make a transition more 
+    /// expensive, keys which are held make it cheaper. With the default model this is just the number
+    /// of keys changed minus the number of keys in common.
+    pub fn transition(&self, from: &Fingering, to: &Fingering) -> f32
+    {
+        let changed = self.weight(from.keys.symmetrical_difference(to.keys));
+        let held = self.weight(from.keys.intersection(to.keys));
+        let slides: f32 = self.fingers
+            .iter()
+            .filter(|finger| !(from.keys - to.keys).is_disjoint(finger.keys) && !(to.keys - from.keys).is_disjoint(finger.keys))
+            .map(|finger| finger.penalty)
+            .sum();
+        changed - held + slides - self.preference(to)
+    }
+
+    /// The bonus given to a fingering for being preferred
+    pub fn preference(&self, fingering: &Fingering) -> f32
+    {
+        if fingering.preferred
+        {
+            self.preferred_bonus
+        }
+        else
+        {
+            0.0
+        }
+    }
+
+    /// The total weight of a set of keys
+    fn weight(&self, keys: EnumSet<Key>) -> f32
+    {
+        keys.iter().map(|key| self.key_weights.get(&key).copied().unwrap_or(1.0)).sum()
+    }
+}
@@ -0,0 +1,102 @@
+use image::error::ImageError;
+use std::{fmt, io, path::PathBuf};
+
+/// Everything that can go wrong while loading configs and songs or writing charts
+#[derive(Debug)]
+pub enum Error
+{
+    /// A file could not be read or written
+    Io(io::Error),
+    /// A midi file could not be parsed
+    Midi(midly::Error),
+    /// A MusicXML file is not well-formed xml
+    Xml(roxmltree::Error),
+    /// A compressed MusicXML file is not a readable zip archive
+    Zip(zip::result::ZipError),
+    /// A score was read but its contents could not be understood
+    Score(String),
+    /// A chart image could not be written
+    Image(ImageError),
+    /// A config file could not be read or parsed
+    Config { path: PathBuf, error: ron::error::Error },
+    /// A note in the config has no fingerings to choose from
+    NoFingerings(u8),
+    /// The config fits no notes in a row
+    NoNotesPerRow
+}
+
+impl fmt::Display for Error
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self
+        {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Midi(e) => write!(f, "Failed to parse midi data: {}", e),
+            Error::Xml(e) => write!(f, "Failed to parse xml: {}", e),
+            Error::Zip(e) => write!(f, "Failed to read compressed MusicXML: {}", e),
+            Error::Score(message) => write!(f, "Failed to read score: {}", message),
+            Error::Image(e) => write!(f, "Failed to write image: {}", e),
+            Error::Config { path, error } => write!(f, "Failed to load config {}: {}", path.display(), error),
+            Error::NoFingerings(byte) => write!(f, "Note {} has no fingerings", byte),
+            Error::NoNotesPerRow => write!(f, "notes_per_row must be at least 1")
+        }
+    }
+}
+
+impl std::error::Error for Error
+{
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)>
+    {
+        match self
+        {
+            Error::Io(e) => Some(e),
+            Error::Midi(e) => Some(e),
+            Error::Xml(e) => Some(e),
+            Error::Zip(e) => Some(e),
+            Error::Image(e) => Some(e),
+            Error::Config { error, .. } => Some(error),
+            Error::Score(_) | Error::NoFingerings(_) | Error::NoNotesPerRow => None
+        }
+    }
+}
+
+impl From<io::Error> for Error
+{
+    fn from(e: io::Error) -> Self
+    {
+        Error::Io(e)
+    }
+}
+
+impl From<midly::Error> for Error
+{
+    fn from(e: midly::Error) -> Self
+    {
+        Error::Midi(e)
+    }
+}
+
+impl From<roxmltree::Error> for Error
+{
+    fn from(e: roxmltree::Error) -> Self
+    {
+        Error::Xml(e)
+    }
+}
+
+impl From<zip::result::ZipError> for Error
+{
+    fn from(e: zip::result::ZipError) -> Self
+    {
+        Error::Zip(e)
+    }
+}
+
+impl From<ImageError> for Error
+{
+    fn from(e: ImageError) -> Self
+    {
+        Error::Image(e)
+    }
+}
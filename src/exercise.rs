@@ -0,0 +1,297 @@
+use crate::{note::{Accidental, Notes, Spelling, LETTERS}, song::{Metadata, RawNote, RawSong}};
+use std::str::FromStr;
+
+/// Ticks per beat used for exercises. Every note is a beat long.
+const TICKS_PER_BEAT: u32 = 480;
+/// The number of notes in each bar of an exercise
+const BEATS_PER_BAR: u32 = 4;
+/// Velocity of every note in an exercise
+const VELOCITY: u8 = 64;
+/// Names of each kind of scale, in the form used on the command line
+const SCALE_NAMES: [(&str, ScaleKind); 14] =
+[
+    ("major", ScaleKind::Major),
+    ("ionian", ScaleKind::Major),
+    ("minor", ScaleKind::NaturalMinor),
+    ("natural-minor", ScaleKind::NaturalMinor),
+    ("aeolian", ScaleKind::NaturalMinor),
+    ("harmonic-minor", ScaleKind::HarmonicMinor),
+    ("melodic-minor", ScaleKind::MelodicMinor),
+    ("chromatic", ScaleKind::Chromatic),
+    ("blues", ScaleKind::Blues),
+    ("dorian", ScaleKind::Dorian),
+    ("phrygian", ScaleKind::Phrygian),
+    ("lydian", ScaleKind::Lydian),
+    ("mixolydian", ScaleKind::Mixolydian),
+    ("locrian", ScaleKind::Locrian)
+];
+/// Names of each pattern, in the form used on the command line
+const PATTERN_NAMES: [(&str, Pattern); 5] =
+[
+    ("ascending", Pattern::Ascending),
+    ("descending", Pattern::Descending),
+    ("up-down", Pattern::UpDown),
+    ("thirds", Pattern::Thirds),
+    ("arpeggio", Pattern::Arpeggio)
+];
+
+/// The kinds of scale exercises are built from
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ScaleKind
+{
+    Major,
+    NaturalMinor,
+    HarmonicMinor,
+    /// Melodic minor raises the sixth and seventh on the way up, and is the same as natural minor on the way down
+    MelodicMinor,
+    Chromatic,
+    Blues,
+    Dorian,
+    Phrygian,
+    Lydian,
+    Mixolydian,
+    Locrian
+}
+
+/// The order the notes of a scale are played in
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Pattern
+{
+    Ascending,
+    Descending,
+    /// Up the scale and back down again
+    UpDown,
+    /// Up the scale in broken thirds and back down again
+    Thirds,
+    /// Up the notes of the scale's tonic chord and back down again
+    Arpeggio
+}
+
+/// A scale or arpeggio to generate a chart for
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Exercise
+{
+    pub tonic: Spelling,
+    pub scale: ScaleKind,
+    pub pattern: Pattern
+}
+
+impl ScaleKind
+{
+    /// The notes of the scale within an octave, as semitones above the tonic along with the number of letters above
+    /// the tonic's letter each is written with. Chromatic notes have no letter, as it depends on the direction.
+    fn degrees(self, descending: bool) -> &'static [(i16, Option<usize>)]
+    {
+        match self
+        {
+            ScaleKind::Major => &[(0, Some(0)), (2, Some(1)), (4, Some(2)), (5, Some(3)), (7, Some(4)), (9, Some(5)), (11, Some(6))],
+            ScaleKind::MelodicMinor if !descending => &[(0, Some(0)), (2, Some(1)), (3, Some(2)), (5, Some(3)), (7, Some(4)), (9, Some(5)), (11, Some(6))],
+            ScaleKind::NaturalMinor | ScaleKind::MelodicMinor => &[(0, Some(0)), (2, Some(1)), (3, Some(2)), (5, Some(3)), (7, Some(4)), (8, Some(5)), (10, Some(6))],
+            ScaleKind::HarmonicMinor => &[(0, Some(0)), (2, Some(1)), (3, Some(2)), (5, Some(3)), (7, Some(4)), (8, Some(5)), (11, Some(6))],
+            ScaleKind::Chromatic => &[(0, Some(0)), (1, None), (2, None), (3, None), (4, None), (5, None), (6, None), (7, None), (8, None), (9, None), (10, None), (11, None)],
+            ScaleKind::Blues => &[(0, Some(0)), (3, Some(2)), (5, Some(3)), (6, Some(4)), (7, Some(4)), (10, Some(6))],
+            ScaleKind::Dorian => &[(0, Some(0)), (2, Some(1)), (3, Some(2)), (5, Some(3)), (7, Some(4)), (9, Some(5)), (10, Some(6))],
+            ScaleKind::Phrygian => &[(0, Some(0)), (1, Some(1)), (3, Some(2)), (5, Some(3)), (7, Some(4)), (8, Some(5)), (10, Some(6))],
+            ScaleKind::Lydian => &[(0, Some(0)), (2, Some(1)), (4, Some(2)), (6, Some(3)), (7, Some(4)), (9, Some(5)), (11, Some(6))],
+            ScaleKind::Mixolydian => &[(0, Some(0)), (2, Some(1)), (4, Some(2)), (5, Some(3)), (7, Some(4)), (9, Some(5)), (10, Some(6))],
+            ScaleKind::Locrian => &[(0, Some(0)), (1, Some(1)), (3, Some(2)), (5, Some(3)), (6, Some(4)), (8, Some(5)), (10, Some(6))]
+        }
+    }
+
+    /// The tonic chord arpeggios are built from, as semitones above the tonic
+    fn chord(self) -> [i16; 3]
+    {
+        match self
+        {
+            ScaleKind::Major | ScaleKind::Chromatic | ScaleKind::Lydian | ScaleKind::Mixolydian => [0, 4, 7],
+            ScaleKind::Locrian => [0, 3, 6],
+            _ => [0, 3, 7]
+        }
+    }
+}
+
+impl Exercise
+{
+    /// A name describing the exercise, such as "Bb major scale" or "F# harmonic minor arpeggio"
+    pub fn name(&self) -> String
+    {
+        let scale = SCALE_NAMES
+            .iter()
+            .find(|&&(_, scale)| scale == self.scale)
+            .map_or("", |&(name, _)| name)
+            .replace('-', " ");
+        match self.pattern
+        {
+            Pattern::Ascending => format!("{} {} scale ascending", self.tonic, scale),
+            Pattern::Descending => format!("{} {} scale descending", self.tonic, scale),
+            Pattern::UpDown => format!("{} {} scale", self.tonic, scale),
+            Pattern::Thirds => format!("{} {} scale in thirds", self.tonic, scale),
+            Pattern::Arpeggio => format!("{} {} arpeggio", self.tonic, scale)
+        }
+    }
+
+    /// The notes of the exercise, in the order they are played. Scales run from the lowest to the highest tonic
+    /// within the range of the given notes, or over the whole range if it is less than an octave.
+    pub fn keys(&self, notes: &Notes) -> Vec<(u8, Option<Spelling>)>
+    {
+        let available: Vec<u8> = (0..=127).filter(|&byte| notes.get(byte).is_some()).collect();
+        let (low, high) = match (available.first(), available.last())
+        {
+            (Some(&low), Some(&high)) => (low as i16, high as i16),
+            _ => return Vec::new()
+        };
+        let tonic = Spelling::natural(self.tonic.letter).unwrap_or_default() + match self.tonic.accidental
+        {
+            Accidental::Natural => 0,
+            Accidental::Flat => -1,
+            Accidental::Sharp => 1
+        };
+        let bottom = low + (tonic - low).rem_euclid(12);
+        let top = high - (high - tonic).rem_euclid(12);
+        let (bottom, top) = if top > bottom { (bottom, top) } else { (low, high) };
+
+        let up = self.run(bottom - (bottom - tonic).rem_euclid(12), bottom, top, false);
+        let mut down = self.run(bottom - (bottom - tonic).rem_euclid(12), bottom, top, true);
+        down.reverse();
+        let chord = self.scale.chord();
+        let in_chord = |&(key, _): &(i16, Option<Spelling>)| chord.contains(&(key - tonic).rem_euclid(12));
+        let keys: Vec<(i16, Option<Spelling>)> = match self.pattern
+        {
+            Pattern::Ascending => up,
+            Pattern::Descending => down,
+            Pattern::UpDown => up.into_iter().chain(down.into_iter().skip(1)).collect(),
+            Pattern::Thirds => up
+                .windows(3)
+                .chain(down.windows(3))
+                .flat_map(|notes| [notes[0], notes[2]])
+                .collect(),
+            Pattern::Arpeggio => up
+                .into_iter()
+                .filter(in_chord)
+                .chain(down.into_iter().filter(in_chord).skip(1))
+                .collect()
+        };
+        keys
+            .into_iter()
+            .filter(|&(key, _)| (0..=127).contains(&key) && notes.get(key as u8).is_some())
+            .map(|(key, spelling)| (key as u8, spelling))
+            .collect()
+    }
+
+    /// The notes of the scale from the bottom to the top key, both included, spelled for the given direction.
+    /// Octaves are counted from the given tonic, which may be below the bottom key.
+    fn run(&self, tonic: i16, bottom: i16, top: i16, descending: bool) -> Vec<(i16, Option<Spelling>)>
+    {
+        let letter = LETTERS.iter().position(|&letter| letter == self.tonic.letter).unwrap_or_default();
+        (0..)
+            .map(|octave| tonic + octave * 12)
+            .take_while(|&octave| octave <= top)
+            .flat_map(|octave| self.scale.degrees(descending).iter().map(move |&(semitones, steps)| (octave + semitones, steps)))
+            .filter(|&(key, _)| key >= bottom && key <= top)
+            .map(|(key, steps)|
+            {
+                // Chromatic notes are written as naturals where possible, otherwise as sharps going up and flats going down
+                let spelling = match steps
+                {
+                    Some(steps) => Spelling::with_letter(key, LETTERS[(letter + steps) % 7]),
+                    None =>
+                    {
+                        let accidental = if descending { Accidental::Flat } else { Accidental::Sharp };
+                        let spellings: Vec<Spelling> = LETTERS.iter().filter_map(|&letter| Spelling::with_letter(key, letter)).collect();
+                        spellings
+                            .iter()
+                            .find(|spelling| spelling.accidental == Accidental::Natural)
+                            .or_else(|| spellings.iter().find(|spelling| spelling.accidental == accidental))
+                            .copied()
+                    }
+                };
+                (key, spelling)
+            })
+            .collect()
+    }
+
+    /// Build a song with a single track for the exercise, with a bar line every four notes
+    pub(crate) fn raw_song(&self, notes: &Notes) -> RawSong
+    {
+        let keys = self.keys(notes);
+        let raw_notes = keys
+            .iter()
+            .enumerate()
+            .map(|(i, &(key, spelling))| RawNote
+            {
+                key: key as i16,
+                velocity: VELOCITY,
+                channel: 0,
+                program: None,
+                start: i as u32 * TICKS_PER_BEAT,
+                duration: TICKS_PER_BEAT,
+                spelling,
+                fingering: None
+            })
+            .collect();
+        let bars = (0..keys.len() as u32).step_by(BEATS_PER_BAR as usize).map(|beat| beat * TICKS_PER_BEAT).collect();
+        RawSong
+        {
+            ticks_per_beat: TICKS_PER_BEAT,
+            bars,
+            metadata: Metadata::default(),
+            tracks: vec![raw_notes],
+            names: vec![(Some(self.name()), None)],
+            key_signatures: Vec::new(),
+            warnings: Vec::new()
+        }
+    }
+}
+
+impl FromStr for Exercise
+{
+    type Err = String;
+
+    /// Read an exercise such as `Bb major`, `F# harmonic-minor thirds` or `D dorian arpeggio`. The scale defaults to
+    /// major and the pattern defaults to up-down.
+    fn from_str(text: &str) -> Result<Exercise, String>
+    {
+        let mut words: Vec<&str> = text.split_whitespace().collect();
+        if words.is_empty()
+        {
+            return Err(String::from("expected a key such as 'Bb major'"));
+        }
+        let tonic = words.remove(0);
+        let mut letters = tonic.chars();
+        let tonic = letters
+            .next()
+            .and_then(|letter|
+            {
+                let accidental = match letters.as_str()
+                {
+                    "" => 0,
+                    "b" => -1,
+                    "#" => 1,
+                    _ => return None
+                };
+                Spelling::with_letter(Spelling::natural(letter)? + accidental, letter)
+            })
+            .ok_or_else(|| format!("invalid key '{}'", tonic))?;
+        let pattern = match words.last().and_then(|word| find(&PATTERN_NAMES, word))
+        {
+            Some(pattern) =>
+            {
+                words.pop();
+                pattern
+            },
+            None => Pattern::UpDown
+        };
+        let scale = match words.join("-").as_str()
+        {
+            "" => ScaleKind::Major,
+            name => find(&SCALE_NAMES, name).ok_or_else(|| format!("unknown scale '{}'", words.join(" ")))?
+        };
+        Ok(Exercise { tonic, scale, pattern })
+    }
+}
+
+/// Look up a name in a list of names, ignoring case
+fn find<T: Copy>(names: &[(&str, T)], word: &str) -> Option<T>
+{
+    names.iter().find(|&&(name, _)| name.eq_ignore_ascii_case(word)).map(|&(_, value)| value)
+}
@@ -1,5 +1,5 @@
 use super::Key;
-use crate::note::Note;
+use crate::{Palette, note::{Note, Spelling, Accidental}};
 use image::{DynamicImage, Rgba};
 
 // Various color constants used in chart generation
@@ -8,17 +8,60 @@ pub const OCTAVE_2_COLOR: Rgba::<u8> = Rgba([164,120,59,255]);
 pub const OCTAVE_3_COLOR: Rgba::<u8> = Rgba([97,90,199,255]);
 pub const OCTAVE_4_COLOR: Rgba::<u8> = Rgba([184,94,191,255]);
 pub const OCTAVE_5_COLOR: Rgba::<u8> = Rgba([76,158,91,255]);
+pub const OCTAVE_6_COLOR: Rgba::<u8> = Rgba([201,82,64,255]);
+pub const OCTAVE_7_COLOR: Rgba::<u8> = Rgba([214,140,26,255]);
+
+/// The octave given the first color of each built-in palette. Lower octaves use the first color as well, and higher 
+/// octaves than a palette has colors for use its last.
+pub const PALETTE_FIRST_OCTAVE: i16 = 2;
+/// Octave colors used by default, from octave 2
+pub const DEFAULT_PALETTE: [Rgba::<u8>; 6] = [OCTAVE_2_COLOR, OCTAVE_3_COLOR, OCTAVE_4_COLOR, OCTAVE_5_COLOR, OCTAVE_6_COLOR, OCTAVE_7_COLOR];
+/// Octave colors from the Okabe-Ito palette, which stay distinct with the common kinds of color blindness
+pub const COLOR_BLIND_PALETTE: [Rgba::<u8>; 6] = 
+[
+    Rgba([230,159,0,255]),
+    Rgba([0,114,178,255]),
+    Rgba([204,121,167,255]),
+    Rgba([0,158,115,255]),
+    Rgba([213,94,0,255]),
+    Rgba([86,180,233,255])
+];
+/// Octave colors which get darker as the octave gets higher, for printing in black and white
+pub const GRAYSCALE_PALETTE: [Rgba::<u8>; 6] = 
+[
+    Rgba([160,160,160,255]),
+    Rgba([128,128,128,255]),
+    Rgba([96,96,96,255]),
+    Rgba([64,64,64,255]),
+    Rgba([32,32,32,255]),
+    Rgba([0,0,0,255])
+];
 
 /// The size of charts, in pixels. The actual charts output may multiply this by a whole-number factor
 pub const CHART_SIZE: (u8,u8) = (63, 118);
 /// The pixel location of the note-name on the chart
 pub const NAME_LOCATION: (u8,u8) = (41,104);
-/// The pixel location of the flat symbol that goes next to the note-name
-pub const FLAT_LOCATION: (u8,u8) = (51,104);
+/// The pixel location of the flat or sharp symbol that goes next to the note-name
+pub const ACCIDENTAL_LOCATION: (u8,u8) = (51,104);
+/// The width of the note-name and accidental symbols
+pub const NAME_WIDTH: u8 = 8;
+/// How far left the note-name and accidental are moved to make room for the octave number, when it is shown
+pub const OCTAVE_SHIFT: u8 = 6;
 /// The pixel location of horizontal separator between front keys
 pub const SEP_LOCATION: (u8,u8) = (20,65);
+/// The pixel location of the rest symbol on a rest chart
+pub const REST_LOCATION: (u8,u8) = (22,56);
+/// The size of the rest symbol, in pixels
+pub const REST_SIZE: (u8,u8) = (20,6);
+/// The height of the duration bar drawn beneath a chart, including a 1 pixel gap above it
+pub const DURATION_HEIGHT: u8 = 5;
+/// The width of bar lines drawn between measures
+pub const BAR_LINE_WIDTH: u8 = 2;
+/// The width of a single beat on the duration bar. Durations longer than will fit on a chart are cut off
+pub const BEAT_WIDTH: u8 = 15;
 
 /// This module is a container for literal raw png image data
+#[allow(clippy::redundant_static_lifetimes)]
 mod raw_image_data
 {
     include!(concat!(env!("OUT_DIR"), "/raw_image_data.rs"));
@@ -36,6 +79,7 @@ lazy_static!
     pub static ref F: DynamicImage = load_image(raw_image_data::F);
     pub static ref G: DynamicImage = load_image(raw_image_data::G);
     pub static ref FLAT: DynamicImage = load_image(raw_image_data::FLAT);
+    pub static ref SHARP: DynamicImage = load_image(raw_image_data::SHARP);
     pub static ref FRONT_KEY: DynamicImage = load_image(raw_image_data::FRONT_KEY);
     pub static ref FRONT_KEY_OFF: DynamicImage = load_image(raw_image_data::FRONT_KEY_OFF);
     pub static ref FRONT_F_KEY: DynamicImage = load_image(raw_image_data::FRONT_F_KEY);
@@ -68,33 +112,75 @@ lazy_static!
 
 impl Note
 {
-    /// Gets the pixel location of a keyname on the fingering chart and the image that goes there
-    pub fn get_image_data<'a>(byte: u8) -> (Rgba::<u8>, &'a DynamicImage, Option<&'a DynamicImage>)
+    /// Gets the color of a note name along with the images for its letter and accidental
+    pub fn get_image_data<'a>(byte: u8, spelling: Spelling, palette: &Palette) -> (Rgba::<u8>, &'a DynamicImage, Option<&'a DynamicImage>)
     {
-        let (name, flat) = match byte % 12
+        let name = match spelling.letter
+        {
+            'A' => &*A,
+            'B' => &*B,
+            'C' => &*C,
+            'D' => &*D,
+            'E' => &*E,
+            'F' => &*F,
+            _ => &*G
+        };
+        let accidental = match spelling.accidental
         {
-            0 => (&*C, None),
-            1 => (&*C, Some(&*FLAT)),
-            2 => (&*D, None),
-            3 => (&*D, Some(&*FLAT)),
-            4 => (&*E, None),
-            5 => (&*F, None),
-            6 => (&*F, Some(&*FLAT)),
-            7 => (&*G, None),
-            8 => (&*G, Some(&*FLAT)),
-            9 => (&*A, None),
-            10 => (&*A, Some(&*FLAT)),
-            11 => (&*B, None),
-            n => panic!("Modulus returned impossible number: {}", n)
+            Accidental::Natural => None,
+            Accidental::Flat => Some(&*FLAT),
+            Accidental::Sharp => Some(&*SHARP)
         };
-        let color = match byte
+        (palette.color(byte), name, accidental)
+    }
+
+    /// Gets the default spelling of a note, which uses flats for black keys
+    pub fn spelling(byte: u8) -> Spelling
+    {
+        let (letter, flat) = match byte % 12
         {
-            n if n < 48 => OCTAVE_2_COLOR,
-            n if n < 60 => OCTAVE_3_COLOR,
-            n if n < 72 => OCTAVE_4_COLOR,
-            _ => OCTAVE_5_COLOR
+            0 => ('C', false),
+            1 => ('D', true),
+            2 => ('D', false),
+            3 => ('E', true),
+            4 => ('E', false),
+            5 => ('F', false),
+            6 => ('G', true),
+            7 => ('G', false),
+            8 => ('A', true),
+            9 => ('A', false),
+            10 => ('B', true),
+            _ => ('B', false)
         };
-        return (color, name, flat);
+        Spelling { letter, accidental: if flat { Accidental::Flat } else { Accidental::Natural } }
+    }
+
+    /// The octave a note is in, where octave 4 starts at middle C
+    pub fn octave(byte: u8) -> i16
+    {
+        byte as i16 / 12 - 1
+    }
+}
+
+impl Palette
+{
+    /// Gets the color used for a note name, which depends on the octave
+    pub fn color(&self, byte: u8) -> Rgba::<u8>
+    {
+        let octave = Note::octave(byte);
+        let preset = |colors: &[Rgba::<u8>]| colors[(octave - PALETTE_FIRST_OCTAVE).clamp(0, colors.len() as i16 - 1) as usize];
+        match self
+        {
+            Palette::Default => preset(&DEFAULT_PALETTE),
+            Palette::ColorBlind => preset(&COLOR_BLIND_PALETTE),
+            Palette::Grayscale => preset(&GRAYSCALE_PALETTE),
+            Palette::Custom(colors) => colors
+                .range(..=octave)
+                .next_back()
+                .or_else(|| colors.iter().next())
+                .map(|(_, &(r, g, b))| Rgba([r, g, b, 255]))
+                .unwrap_or_else(|| Palette::Default.color(byte))
+        }
     }
 }
 
@@ -1,10 +1,13 @@
 pub(crate) mod image_data;
+pub(crate) mod font;
 
-use crate::note::{Note, Fingering};
+use crate::{Config, note::{Note, Fingering, Spelling}, theme::{Color, Theme}};
+use font::{draw_text, GLYPH_HEIGHT};
 use image_data::*;
 use enumset::*;
-use image::{DynamicImage, GenericImage, GenericImageView};
+use image::{DynamicImage, GenericImage, GenericImageView, Pixel, RgbImage, RgbaImage, imageops};
 use serde::{Serialize, Deserialize};
+use std::hash::{Hash, Hasher};
 
 #[derive(EnumSetType, Debug, Deserialize, Serialize)]
 #[enumset(serialize_as_list)]
@@ -37,42 +40,54 @@ pub enum Key
     LowA
 }
 
-impl Fingering
+// Hash by discriminant, so keys can be used in config maps
+impl Hash for Key
 {
-    /// Fingering charts are generated here. Note names must be added after
-    pub fn gen_chart(keys: EnumSet<Key>) -> DynamicImage
+    fn hash<H: Hasher>(&self, state: &mut H)
     {
-        let mut chart = gen_base_chart();
+        (*self as u8).hash(state);
+    }
+}
 
-        // Depending on which keys are visible, show more greyed-out keys
-        if !keys.is_disjoint(Key::LowEflat | Key::LowC)
-        {
-            Key::LowEflat.include_key_image(false, &mut chart);
-            Key::LowC.include_key_image(false, &mut chart);
-        }
-        if !keys.is_disjoint(Key::Gsharp | Key::LowCsharp | Key::LowB | Key::LowBflat)
-        {
-            Key::Gsharp.include_key_image(false, &mut chart);
-            Key::LowCsharp.include_key_image(false, &mut chart);
-            Key::LowB.include_key_image(false, &mut chart);
-            Key::LowBflat.include_key_image(false, &mut chart);
-        }
-        if !keys.is_disjoint(Key::PalmD | Key::PalmEflat | Key::PalmF)
-        {
-            Key::PalmD.include_key_image(false, &mut chart);
-            Key::PalmEflat.include_key_image(false, &mut chart);
-            Key::PalmF.include_key_image(false, &mut chart);
-        }
-        if !keys.is_disjoint(Key::SideE | Key::SideC | Key::SideBis | Key::Fsharp | Key::HighFsharp)
+/// Keys which are shown on every chart, even when they aren't used
+pub const BASE_KEYS: EnumSet<Key> = enum_set!(Key::Octave | Key::Left1 | Key::Left2 | Key::Left3 | Key::Right1 | Key::Right2 | Key::Right3);
+
+/// Determine which keys are shown on a chart. Depending on which keys are used, more greyed-out keys are shown.
+pub fn visible_keys(keys: EnumSet<Key>) -> EnumSet<Key>
+{
+    let mut visible = BASE_KEYS | keys;
+    if !keys.is_disjoint(Key::LowEflat | Key::LowC)
+    {
+        visible |= Key::LowEflat | Key::LowC;
+    }
+    if !keys.is_disjoint(Key::Gsharp | Key::LowCsharp | Key::LowB | Key::LowBflat)
+    {
+        visible |= Key::Gsharp | Key::LowCsharp | Key::LowB | Key::LowBflat;
+    }
+    if !keys.is_disjoint(Key::PalmD | Key::PalmEflat | Key::PalmF)
+    {
+        visible |= Key::PalmD | Key::PalmEflat | Key::PalmF;
+    }
+    if !keys.is_disjoint(Key::SideE | Key::SideC | Key::SideBis | Key::Fsharp | Key::HighFsharp)
+    {
+        visible |= Key::SideE | Key::SideC | Key::SideBis;
+    }
+    visible
+}
+
+impl Fingering
+{
+    /// Fingering charts are generated here, in the colors of the given theme. Note names must be added after
+    pub fn gen_chart(keys: EnumSet<Key>, theme: &Theme) -> DynamicImage
+    {
+        let mut chart = gen_base_chart(theme);
+        for key in visible_keys(keys) - BASE_KEYS - keys
         {
-            Key::SideE.include_key_image(false, &mut chart);
-            Key::SideC.include_key_image(false, &mut chart);
-            Key::SideBis.include_key_image(false, &mut chart);
+            key.include_key_image(false, theme, &mut chart);
         }
-
         for key in keys
         {
-            key.include_key_image(true, &mut chart);
+            key.include_key_image(true, theme, &mut chart);
         }
         chart
     }
@@ -80,60 +95,170 @@ impl Fingering
 
 impl Note
 {
-    pub fn include_note_name(byte: u8, image: &mut DynamicImage)
+    /// Draw the name of a note onto its chart, spelled as given, in the color of its octave. If enabled the octave
+    /// number is drawn after the name, which is moved left to make room.
+    pub fn include_note_name(byte: u8, spelling: Spelling, config: &Config, theme: &Theme, image: &mut DynamicImage)
     {
-        let (color, name, flat) = Note::get_image_data(byte);
-        for (x, y, inner_color) in name.pixels()
+        let (color, name, accidental) = Note::get_image_data(byte, spelling, &config.octave_colors);
+        let color = theme.note_names.map_or(color, Color::rgba);
+        let shift = if config.show_octave { OCTAVE_SHIFT as u32 } else { 0 };
+        let mut symbols = vec![(name, NAME_LOCATION.0 as u32 - shift)];
+        symbols.extend(accidental.map(|accidental| (accidental, ACCIDENTAL_LOCATION.0 as u32 - shift)));
+        for &(symbol, left) in &symbols
         {
-            if inner_color == BLACK
-            {
-                let x = x + NAME_LOCATION.0 as u32;
-                let y = y + NAME_LOCATION.1 as u32;
-                image.put_pixel(x, y, color)
-            }
-        }
-        if let Some(flat) = flat
-        {
-            for (x, y, inner_color) in flat.pixels()
+            for (x, y, inner_color) in symbol.pixels()
             {
                 if inner_color == BLACK
                 {
-                    let x = x + FLAT_LOCATION.0 as u32;
-                    let y = y + FLAT_LOCATION.1 as u32;
-                    image.put_pixel(x, y, color)
+                    image.put_pixel(x + left, y + NAME_LOCATION.1 as u32, color)
                 }
             }
         }
+        if config.show_octave
+        {
+            let left = symbols.last().map_or(0, |&(_, left)| left) + NAME_WIDTH as u32 + 1;
+            let top = NAME_LOCATION.1 as u32 + name.height() - GLYPH_HEIGHT - 1;
+            draw_text(image, &Note::octave(byte).to_string(), left, top, color);
+        }
     }
 }
 
 impl Key
 {
-    /// Include this key image on a chart
-    fn include_key_image(&self, filled: bool, image: &mut DynamicImage)
+    /// Include this key image on a chart, in the theme's color for filled or unfilled keys
+    fn include_key_image(&self, filled: bool, theme: &Theme, image: &mut DynamicImage)
     {
         let (x, y, note_data, note_off_data) = self.get_image_data();
-        image.copy_from(if filled {note_data} else {note_off_data}, x as u32, y as u32)
-            .expect("Failed to generate key image");
+        let (sprite, color) = if filled { (note_data, theme.filled) } else { (note_off_data, theme.unfilled) };
+        include_sprite(sprite, x as u32, y as u32, color, theme, image);
+    }
+}
+
+/// Copy a sprite onto a chart. The sprite's shape is drawn in the given color, and the rest of it in the theme's 
+/// chart background, so it covers anything drawn there before.
+fn include_sprite(sprite: &DynamicImage, x: u32, y: u32, color: Color, theme: &Theme, image: &mut DynamicImage)
+{
+    for (sprite_x, sprite_y, pixel) in sprite.pixels()
+    {
+        let pixel = if pixel[3] == 0 { theme.chart_background } else { color };
+        image.put_pixel(x + sprite_x, y + sprite_y, pixel.rgba());
     }
 }
 
+/// Generates an empty chart filled with the theme's chart background
+fn gen_blank_chart(width: u32, height: u32, theme: &Theme) -> DynamicImage
+{
+    DynamicImage::ImageRgba8(RgbaImage::from_pixel(width, height, theme.chart_background.rgba()))
+}
+
+/// Generates an empty image filled with the theme's background, for laying out charts and text on
+pub fn gen_background(width: u32, height: u32, theme: &Theme) -> DynamicImage
+{
+    DynamicImage::ImageRgb8(RgbImage::from_pixel(width, height, theme.background.rgb()))
+}
+
 /// Generates a blank fingering chart
-fn gen_base_chart() -> DynamicImage
-{
-    let mut image: DynamicImage = DynamicImage::new_rgba8(CHART_SIZE.0 as u32, CHART_SIZE.1 as u32);
-    image.invert();
-
-    // All charts start with 6 unfilled notes and a separator
-    Key::Octave.include_key_image(false, &mut image);
-    Key::Left1.include_key_image(false, &mut image);
-    Key::Left2.include_key_image(false, &mut image);
-    Key::Left3.include_key_image(false, &mut image);
-    Key::Right1.include_key_image(false, &mut image);
-    Key::Right2.include_key_image(false, &mut image);
-    Key::Right3.include_key_image(false, &mut image);
-    image.copy_from(&*SEPARATOR, SEP_LOCATION.0 as u32, SEP_LOCATION.1 as u32)
-        .expect("Failed to generate separator image");
+fn gen_base_chart(theme: &Theme) -> DynamicImage
+{
+    let mut image = gen_blank_chart(CHART_SIZE.0 as u32, CHART_SIZE.1 as u32, theme);
+
+    // All charts start with the octave key, 6 unfilled notes and a separator
+    for key in BASE_KEYS
+    {
+        key.include_key_image(false, theme, &mut image);
+    }
+    include_sprite(&SEPARATOR, SEP_LOCATION.0 as u32, SEP_LOCATION.1 as u32, theme.separator, theme, &mut image);
+    image
+}
+
+/// Generates a chart for a rest. No keys are shown, only a rest symbol
+pub fn gen_rest_chart(theme: &Theme) -> DynamicImage
+{
+    let mut image = gen_blank_chart(CHART_SIZE.0 as u32, CHART_SIZE.1 as u32, theme);
+    let color = theme.filled.rgba();
+
+    // Draw a half-rest: a block sitting on top of a line
+    let (x, y) = (REST_LOCATION.0 as u32, REST_LOCATION.1 as u32);
+    let (width, height) = (REST_SIZE.0 as u32, REST_SIZE.1 as u32);
+    for i in 0..width
+    {
+        image.put_pixel(x + i, y + height - 1, color);
+    }
+    for i in (width / 4)..(width - width / 4)
+    {
+        for j in 0..(height - 1)
+        {
+            image.put_pixel(x + i, y + j, color);
+        }
+    }
     image
 }
 
+/// Generates a bar line to separate measures. Bar lines are drawn between charts, so they use the theme's font color.
+pub fn gen_bar_line(height: u32, theme: &Theme) -> DynamicImage
+{
+    DynamicImage::ImageRgba8(RgbaImage::from_pixel(BAR_LINE_WIDTH as u32, height, theme.font.rgba()))
+}
+
+/// Creates a copy of a chart with a duration bar drawn beneath it. Each beat is separated by a notch.
+pub fn with_duration(image: &DynamicImage, beats: f32, theme: &Theme) -> DynamicImage
+{
+    let mut chart = gen_blank_chart(image.width(), image.height() + DURATION_HEIGHT as u32, theme);
+    imageops::replace(&mut chart, image, 0, 0);
+    let color = theme.filled.rgba();
+
+    // Always draw at least a single pixel so very short notes are still visible
+    let max_width = image.width() - 2;
+    let width = ((beats * BEAT_WIDTH as f32).round() as u32).clamp(1, max_width);
+    for x in 0..width
+    {
+        if x % BEAT_WIDTH as u32 == BEAT_WIDTH as u32 - 1
+        {
+            continue;
+        }
+        for y in 1..DURATION_HEIGHT as u32
+        {
+            chart.put_pixel(x + 1, image.height() + y, color);
+        }
+    }
+    chart
+}
+
+/// Creates a copy of a chart with the theme's padding around it, filled with the chart background
+pub fn with_padding(image: &DynamicImage, theme: &Theme) -> DynamicImage
+{
+    if theme.padding == 0
+    {
+        return image.clone();
+    }
+    let mut chart = gen_blank_chart(image.width() + theme.padding * 2, image.height() + theme.padding * 2, theme);
+    imageops::replace(&mut chart, image, theme.padding as i64, theme.padding as i64);
+    chart
+}
+
+/// Scales an image up by a whole number, so every pixel becomes a square block of pixels
+pub fn scaled(image: &DynamicImage, scale: u32) -> DynamicImage
+{
+    if scale <= 1
+    {
+        return image.clone();
+    }
+    image.resize_exact(image.width() * scale, image.height() * scale, imageops::FilterType::Nearest)
+}
+
+/// Copies an image onto a canvas with its top left corner at the given position, scaling it up by a whole number as 
+/// it is copied so no scaled copy of the image is ever made. Transparency is ignored.
+pub fn blit_scaled(canvas: &mut RgbImage, image: &DynamicImage, x: u32, y: u32, scale: u32)
+{
+    for (image_x, image_y, pixel) in image.pixels()
+    {
+        let pixel = pixel.to_rgb();
+        for canvas_y in y + image_y * scale..y + (image_y + 1) * scale
+        {
+            for canvas_x in x + image_x * scale..x + (image_x + 1) * scale
+            {
+                canvas.put_pixel(canvas_x, canvas_y, pixel);
+            }
+        }
+    }
+}
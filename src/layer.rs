@@ -0,0 +1,169 @@
+use crate::{Config, Error, TranspositionType, OutputFormat, FileType, Sizes, Layout, SplitBy, RepeatedNotes, Polyphony, FingeringSelection, KeySignatures, Palette, cost::CostModel, pdf::PdfConfig, note::FingeringConfig, theme::Themes};
+use ron::de::from_str;
+use serde::{Deserialize, Deserializer};
+use std::{env, fs, path::{Path, PathBuf}, collections::BTreeMap};
+
+/// The config shipped with the program, used as the bottom layer which every user config is merged on top of
+pub const DEFAULT_CONFIG: &str = include_str!("../cfg.ron");
+/// The file name searched for in each config location
+const CONFIG_FILE_NAME: &str = "cfg.ron";
+/// The directory searched for within the XDG config directory
+const CONFIG_DIR_NAME: &str = "sax-fingering-chart";
+
+/// A user config file, in which every field is optional. Fields which are set replace the value from the layers
+/// beneath, except `notes` where only the listed notes are replaced.
+#[derive(Deserialize, Default)]
+#[serde(rename = "Config", default)]
+pub struct ConfigLayer
+{
+    #[serde(deserialize_with = "some")]
+    transposition_type: Option<TranspositionType>,
+    #[serde(deserialize_with = "some")]
+    auto_transpose: Option<bool>,
+    #[serde(deserialize_with = "some")]
+    output_path: Option<String>,
+    #[serde(deserialize_with = "some")]
+    output_format: Option<OutputFormat>,
+    #[serde(deserialize_with = "some")]
+    file_type: Option<FileType>,
+    #[serde(deserialize_with = "some")]
+    spacing: Option<usize>,
+    #[serde(deserialize_with = "some")]
+    notes_per_row: Option<usize>,
+    #[serde(deserialize_with = "some")]
+    scale: Option<u32>,
+    #[serde(deserialize_with = "some")]
+    sizes: Option<Sizes>,
+    #[serde(deserialize_with = "some")]
+    tracks: Option<Vec<usize>>,
+    #[serde(deserialize_with = "some")]
+    track_names: Option<Vec<String>>,
+    #[serde(deserialize_with = "some")]
+    channels: Option<Vec<u8>>,
+    #[serde(deserialize_with = "some")]
+    skip_percussion: Option<bool>,
+    #[serde(deserialize_with = "some")]
+    min_notes: Option<usize>,
+    #[serde(deserialize_with = "some")]
+    layout: Option<Layout>,
+    #[serde(deserialize_with = "some")]
+    show_rests: Option<bool>,
+    #[serde(deserialize_with = "some")]
+    show_durations: Option<bool>,
+    #[serde(deserialize_with = "some")]
+    show_title: Option<bool>,
+    #[serde(deserialize_with = "some")]
+    show_octave: Option<bool>,
+    #[serde(deserialize_with = "some")]
+    octave_colors: Option<Palette>,
+    #[serde(deserialize_with = "some")]
+    theme: Option<Themes>,
+    #[serde(deserialize_with = "some")]
+    split_by: Option<SplitBy>,
+    #[serde(deserialize_with = "some")]
+    repeated_notes: Option<RepeatedNotes>,
+    #[serde(deserialize_with = "some")]
+    polyphony: Option<Polyphony>,
+    #[serde(deserialize_with = "some")]
+    fingering_selection: Option<FingeringSelection>,
+    #[serde(deserialize_with = "some")]
+    key_signature: Option<KeySignatures>,
+    #[serde(deserialize_with = "some")]
+    costs: Option<CostModel>,
+    #[serde(deserialize_with = "some")]
+    pdf: Option<PdfConfig>,
+    notes: BTreeMap<u8, Vec<FingeringConfig>>
+}
+
+impl ConfigLayer
+{
+    /// Read and parse a config file
+    pub fn load(path: &Path) -> Result<ConfigLayer, Error>
+    {
+        fs::read_to_string(path)
+            .map_err(ron::error::Error::from)
+            .and_then(|str| from_str(&str))
+            .map_err(|error| Error::Config { path: path.to_path_buf(), error })
+    }
+
+    /// Override config values with any values set in this layer
+    pub fn apply(self, config: &mut Config)
+    {
+        macro_rules! merge
+        {
+            ($($field:ident),*) =>
+            {
+                $(
+                    if let Some(value) = self.$field
+                    {
+                        config.$field = value;
+                    }
+                )*
+            };
+        }
+        merge!(transposition_type, auto_transpose, output_path, output_format, file_type, spacing, notes_per_row, scale, sizes,
+            tracks, track_names, channels, skip_percussion, min_notes, layout, show_rests, show_durations, show_title,
+            show_octave, octave_colors, theme, split_by, repeated_notes, polyphony, fingering_selection,
+            key_signature, costs, pdf);
+        config.notes.extend(self.notes);
+    }
+}
+
+/// Load the embedded default config, then merge every user config found on top of it. Configs are searched for
+/// next to the executable and in the XDG config directory. Config files closer to the user take priority, with
+/// the working directory above those, and an explicit path above everything. An explicit path must exist.
+pub fn load(explicit: Option<&str>) -> Result<Config, Error>
+{
+    let mut config: Config = from_str(DEFAULT_CONFIG)
+        .map_err(|error| Error::Config { path: PathBuf::from("<default config>"), error })?;
+
+    let mut paths: Vec<PathBuf> = Vec::new();
+    for path in search_paths().into_iter().filter(|path| path.is_file())
+    {
+        // The working directory may be the same as the executable's directory, don't load the same file twice
+        let canonical = path.canonicalize().unwrap_or(path);
+        if !paths.contains(&canonical)
+        {
+            paths.push(canonical);
+        }
+    }
+    for path in &paths
+    {
+        ConfigLayer::load(path)?.apply(&mut config);
+    }
+    if let Some(path) = explicit
+    {
+        ConfigLayer::load(Path::new(path))?.apply(&mut config);
+    }
+    Ok(config)
+}
+
+/// Locations config files are read from, from lowest to highest priority
+fn search_paths() -> Vec<PathBuf>
+{
+    let xdg_dir = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")));
+    let exe_dir = env::current_exe().ok().and_then(|exe| exe.parent().map(Path::to_path_buf));
+    let mut paths = Vec::new();
+    if let Some(dir) = xdg_dir
+    {
+        paths.push(dir.join(CONFIG_DIR_NAME).join(CONFIG_FILE_NAME));
+    }
+    if let Some(dir) = exe_dir
+    {
+        paths.push(dir.join(CONFIG_FILE_NAME));
+    }
+    paths.push(PathBuf::from(CONFIG_FILE_NAME));
+    paths
+}
+
+/// Deserialize a value that is present in a config layer, since values in ron files are not written as `Some(...)`
+pub(crate) fn some<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>
+{
+    T::deserialize(deserializer).map(Some)
+}
@@ -0,0 +1,314 @@
+//! Generates saxophone fingering charts from midi files. Load a `Config`, build the `Notes` it defines, then load
+//! a `Song` and render its tracks as images, svgs or pdfs.
+
+#[macro_use]
+extern crate lazy_static;
+
+pub mod note;
+pub mod keys;
+pub mod track;
+pub mod song;
+pub mod musicxml;
+pub mod abc;
+pub mod text;
+pub mod exercise;
+pub mod reference;
+pub mod cost;
+pub mod svg;
+pub mod pdf;
+pub mod theme;
+#[cfg(feature = "cli")]
+pub mod cli;
+pub mod layer;
+pub mod error;
+pub mod instrument;
+
+#[cfg(test)]
+mod tests;
+
+pub use error::Error;
+pub use song::Song;
+pub use track::Track;
+pub use note::{Note, Notes, Fingering};
+pub use keys::Key;
+
+use serde::Deserialize;
+use std::{collections::BTreeMap, str::FromStr};
+use note::{FingeringConfig, KeySignature};
+use cost::CostModel;
+use pdf::PdfConfig;
+use theme::Themes;
+
+/// The note transposition to use, supports saxes of any kind
+#[derive(Copy, Clone, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum TranspositionType
+{
+    Sopranissimo = -22,
+    Sopranino = -15,
+    Soprano = -10,
+    Alto = -3,
+    CMelody = 0,
+    Tenor = 2,
+    Baritone = 9,
+    Bass = 14,
+    Contrabass = 21,
+    Subcontrabass = 26
+}
+
+/// The output format, determines how the chart images are layed out.
+#[derive(Copy, Clone, Deserialize, PartialEq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum OutputFormat
+{
+    Separate,
+    Rows,
+    Tracks,
+    Pdf
+}
+
+/// The type of file charts are written to.
+#[derive(Copy, Clone, Deserialize, PartialEq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum FileType
+{
+    #[default]
+    Png,
+    Svg
+}
+
+/// How large charts are written. Charts are always scaled up by a whole number so every pixel stays sharp.
+#[derive(Copy, Clone, Deserialize, PartialEq, Debug)]
+pub enum Size
+{
+    /// Every pixel of a chart becomes a square this many pixels wide
+    Scale(u32),
+    /// The largest scale at which each file is no wider than this many pixels
+    Width(u32),
+    /// The scale closest to this many pixels per inch, where an unscaled chart pixel prints as a point (1/72 inch)
+    /// as it does in pdf output
+    Dpi(u32)
+}
+
+/// Points in an inch, the print size of an unscaled chart pixel
+const POINTS_PER_INCH: u32 = 72;
+
+impl Size
+{
+    /// The factor an image of the given unscaled width is scaled up by, which is never less than 1
+    pub fn scale(&self, width: u32) -> u32
+    {
+        match *self
+        {
+            Size::Scale(scale) => scale,
+            Size::Width(target) => target / width.max(1),
+            Size::Dpi(dpi) => (dpi + POINTS_PER_INCH / 2) / POINTS_PER_INCH
+        }.max(1)
+    }
+}
+
+/// Sizes for individual output formats, which override `scale` for that format
+#[derive(Copy, Clone, Deserialize, PartialEq, Default, Debug)]
+#[serde(default)]
+pub struct Sizes
+{
+    #[serde(deserialize_with = "layer::some")]
+    pub separate: Option<Size>,
+    #[serde(deserialize_with = "layer::some")]
+    pub rows: Option<Size>,
+    #[serde(deserialize_with = "layer::some")]
+    pub tracks: Option<Size>
+}
+
+impl Sizes
+{
+    /// The same size for every output format
+    pub fn all(size: Size) -> Sizes
+    {
+        Sizes { separate: Some(size), rows: Some(size), tracks: Some(size) }
+    }
+}
+
+/// The row layout, determines where rows of charts wrap.
+#[derive(Copy, Clone, Deserialize, PartialEq, Default)]
+pub enum Layout
+{
+    #[default]
+    Notes,
+    Measures
+}
+
+/// The algorithm used to pick between alternate fingerings.
+#[derive(Copy, Clone, Deserialize, PartialEq, Default)]
+pub enum FingeringSelection
+{
+    #[default]
+    Greedy,
+    Optimal
+}
+
+/// How the notes of a midi file are split up into tracks.
+#[derive(Copy, Clone, Deserialize, PartialEq, Default)]
+pub enum SplitBy
+{
+    /// Each track in the midi file becomes a track
+    #[default]
+    Track,
+    /// Notes are grouped by midi channel, across every track in the file
+    Channel,
+    /// Notes are grouped by midi channel and the program (instrument) they were played with
+    Program
+}
+
+/// How a note is handled when it is started again while it is still being held.
+#[derive(Copy, Clone, Deserialize, PartialEq, Default)]
+pub enum RepeatedNotes
+{
+    /// The held note ends and a new note starts, which ends once every start has been matched by an end
+    #[default]
+    Retrigger,
+    /// The held note carries on, and only ends once every start has been matched by an end
+    Legato
+}
+
+/// How notes played at the same time are handled, since a saxophone can only play one at a time.
+#[derive(Copy, Clone, Deserialize, PartialEq, Default)]
+pub enum Polyphony
+{
+    /// Every note is charted
+    #[default]
+    Keep,
+    /// Only the highest note of each chord is kept, which is usually the melody
+    Highest,
+    /// Only the lowest note of each chord is kept
+    Lowest,
+    /// Only the loudest note of each chord is kept
+    Loudest,
+    /// Each voice of a chord is put into its own track, from highest to lowest
+    Split
+}
+
+/// The colors note names are drawn in, which depend on the octave
+#[derive(Clone, Deserialize, PartialEq, Default, Debug)]
+pub enum Palette
+{
+    /// Brown, blue, purple and green from the lowest notes up, then red and orange in the altissimo
+    #[default]
+    Default,
+    /// Colors which stay distinct with the common kinds of color blindness
+    ColorBlind,
+    /// Shades of gray, darker for higher octaves
+    Grayscale,
+    /// A color for each octave, such as `{4: (184, 94, 191)}`, where octave 4 starts at middle C. Octaves without a
+    /// color use the closest octave below that has one.
+    Custom(BTreeMap<i16, (u8, u8, u8)>)
+}
+
+/// Where the key signature that note names are spelled in comes from. Keys are given at concert pitch and are 
+/// transposed along with the notes.
+#[derive(Copy, Clone, Deserialize, PartialEq, Default, Debug)]
+pub enum KeySignatures
+{
+    /// Key signatures are read from the file, or detected from the notes if the file has none
+    #[default]
+    Auto,
+    /// The key is always detected from the notes, ignoring any key signatures in the file
+    Detect,
+    /// Every song is spelled in this key, such as `Fixed("Eb")` or `Fixed("F#m")`
+    Fixed(KeySignature)
+}
+
+impl FromStr for KeySignatures
+{
+    type Err = String;
+
+    /// Read `auto`, `detect` or a key such as `Eb` or `F# minor`
+    fn from_str(text: &str) -> Result<KeySignatures, String>
+    {
+        match text.to_lowercase().as_str()
+        {
+            "auto" => Ok(KeySignatures::Auto),
+            "detect" => Ok(KeySignatures::Detect),
+            _ => text.parse().map(KeySignatures::Fixed)
+        }
+    }
+}
+
+/// Data from the loaded cfg.ron file. Use `layer::load` to load the default config merged with any user configs.
+#[derive(Deserialize)]
+pub struct Config 
+{
+    pub transposition_type: TranspositionType,
+    #[serde(default)]
+    pub auto_transpose: bool,
+    pub output_path: String,
+    pub output_format: OutputFormat,
+    #[serde(default)]
+    pub file_type: FileType,
+    pub spacing: usize,
+    pub notes_per_row: usize,
+    #[serde(default = "default_scale")]
+    pub scale: u32,
+    #[serde(default)]
+    pub sizes: Sizes,
+    #[serde(default)]
+    pub tracks: Vec<usize>,
+    #[serde(default)]
+    pub track_names: Vec<String>,
+    #[serde(default)]
+    pub channels: Vec<u8>,
+    #[serde(default)]
+    pub skip_percussion: bool,
+    #[serde(default)]
+    pub min_notes: usize,
+    #[serde(default)]
+    pub layout: Layout,
+    #[serde(default)]
+    pub show_rests: bool,
+    #[serde(default)]
+    pub show_durations: bool,
+    #[serde(default)]
+    pub show_title: bool,
+    #[serde(default)]
+    pub show_octave: bool,
+    #[serde(default)]
+    pub octave_colors: Palette,
+    #[serde(default)]
+    pub theme: Themes,
+    #[serde(default)]
+    pub split_by: SplitBy,
+    #[serde(default)]
+    pub repeated_notes: RepeatedNotes,
+    #[serde(default)]
+    pub polyphony: Polyphony,
+    #[serde(default)]
+    pub fingering_selection: FingeringSelection,
+    #[serde(default)]
+    pub key_signature: KeySignatures,
+    #[serde(default)]
+    pub costs: CostModel,
+    #[serde(default)]
+    pub pdf: PdfConfig,
+    pub notes: BTreeMap<u8, Vec<FingeringConfig>> 
+}
+
+impl Config
+{
+    /// The size charts are written at in the given output format. Pdf output is laid out to fit the page instead.
+    pub fn size(&self, format: OutputFormat) -> Size
+    {
+        match format
+        {
+            OutputFormat::Separate => self.sizes.separate,
+            OutputFormat::Rows => self.sizes.rows,
+            OutputFormat::Tracks => self.sizes.tracks,
+            OutputFormat::Pdf => None
+        }.unwrap_or(Size::Scale(self.scale))
+    }
+}
+
+/// Charts are output at twice their size unless otherwise configured
+fn default_scale() -> u32
+{
+    2
+}
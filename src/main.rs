@@ -1,91 +1,119 @@
+use sax_fingering_chart::{cli::Args, exercise::Exercise, layer, reference::output_reference, song::AutoTransposition, track::file_name, Config, Error, Notes, OutputFormat, Polyphony, Song};
+use clap::Parser;
+use std::process::ExitCode;
 
-#[macro_use]
-extern crate lazy_static;
-
-#[macro_use]
-mod note;
-mod keys;
-mod track;
-mod song;
-
-#[cfg(test)]
-mod tests;
-
-use song::*;
-use ron::de::from_str;
-use serde::Deserialize;
-use std::{env, path::Path, collections::BTreeMap};
-use image::{error::ImageError};
-use enumset::*;
-use keys::Key;
-
-// Load configuration before executing main program
-lazy_static!
+/// Entry-point
+fn main() -> ExitCode
 {
-    static ref CONFIG: Result<Config, ron::error::Error> = 
-    std::fs::read_to_string("./cfg.ron")
-        .map_err(ron::error::Error::from)
-        .and_then(|str| from_str(&str));
-}
+    let args = Args::parse();
+    let mut config = match layer::load(args.config.as_deref())
+    {
+        Ok(config) => config,
+        Err(e) =>
+        {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    args.apply(&mut config);
+    let notes = match Notes::new(&config)
+    {
+        Ok(notes) => notes,
+        Err(e) =>
+        {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
 
-/// The note transposition to use, supports saxes of any kind
-#[derive(Copy, Clone, Deserialize)]
-pub enum TranspositionType
-{
-    Sopranissimo = -22,
-    Sopranino = -15,
-    Soprano = -10,
-    Alto = -3,
-    CMelody = 0,
-    Tenor = 2,
-    Baritone = 9,
-    Bass = 14,
-    Contrabass = 21,
-    Subcontrabass = 26
+    let mut result = ExitCode::SUCCESS;
+    for file in &args.files
+    {
+        if let Err(e) = output_song(file, &notes, &config)
+        {
+            eprintln!("Failed to output charts for {}: {}", file, e);
+            result = ExitCode::FAILURE;
+        }
+    }
+    for exercise in &args.exercises
+    {
+        if let Err(e) = output_exercise(exercise, &notes, &config)
+        {
+            eprintln!("Failed to output charts for {}: {}", exercise.name(), e);
+            result = ExitCode::FAILURE;
+        }
+    }
+    if args.reference
+    {
+        if let Err(e) = output_reference(&notes, &config.output_path, &config)
+        {
+            eprintln!("Failed to output the fingering reference: {}", e);
+            result = ExitCode::FAILURE;
+        }
+    }
+    result
 }
 
-/// The output format, determines how the chart images are layed out.
-#[derive(Copy, Clone, Deserialize, PartialEq)]
-pub enum OutputFormat
+/// Generate charts for a single song file, or for a note list read from standard input
+fn output_song(file: &str, notes: &Notes, config: &Config) -> Result<(), Error>
 {
-    Separate,
-    Rows,
-    Tracks
+    let fingering_chart = Song::load(file, notes, config)?;
+    report(file, &fingering_chart, config);
+    output(&fingering_chart, &fingering_chart.output_name(file), config)
 }
 
-/// Data from the loaded cfg.ron file.
-#[derive(Deserialize)]
-pub struct Config 
+/// Generate charts for a scale or arpeggio exercise
+fn output_exercise(exercise: &Exercise, notes: &Notes, config: &Config) -> Result<(), Error>
 {
-    transposition_type: TranspositionType,
-    output_path: String,
-    output_format: OutputFormat,
-    spacing: usize,
-    notes_per_row: usize,
-    notes: BTreeMap<u8, Vec<EnumSet<Key>>> 
+    let fingering_chart = Song::exercise(exercise, notes, config);
+    report(&exercise.name(), &fingering_chart, config);
+    output(&fingering_chart, &file_name(&exercise.name()), config)
 }
 
-/// Entry-point
-fn main() -> Result<(), ImageError>
+/// Print what was done to a song while it was loaded, and which of its tracks are skipped
+fn report(name: &str, song: &Song, config: &Config)
 {
-    match &*CONFIG
+    let report = &song.report;
+    for warning in &report.warnings
     {
-        Ok(config) =>
+        eprintln!("{}", warning);
+    }
+    if report.reduced_notes > 0
+    {
+        match config.polyphony
         {
-            for midi_file in env::args().skip(1)
-            {
-                let fingering_chart = Song::load(&midi_file, config.transposition_type as i16);
-                let dir_name = Path::new(&midi_file).file_stem().unwrap().to_string_lossy();
-                let output_path = format!("{}/{}", config.output_path, dir_name);
-                match config.output_format
-                {
-                    OutputFormat::Tracks => fingering_chart.output_entire(&output_path, config.notes_per_row, config.spacing)?,
-                    OutputFormat::Rows => fingering_chart.output_rows(&output_path, config.notes_per_row, config.spacing)?,
-                    OutputFormat::Separate => fingering_chart.output_cells(&output_path)?
-                }
-            }
+            Polyphony::Split => println!("{}: split {} note(s) in chords into {} extra voice track(s)", name, report.reduced_notes, report.extra_voices),
+            _ => println!("{}: reduced {} note(s) in chords to a single note", name, report.reduced_notes)
         }
-        Err(e) => eprintln!("Failed to load config: {}", e)
     }
-    Ok(())
-}
\ No newline at end of file
+    if let Some(key) = report.detected_key
+    {
+        println!("{}: detected the key of {}, spelling notes to suit it", name, key);
+    }
+    if let Some(AutoTransposition { low, high, shift }) = report.transposition
+    {
+        println!("{}: notes span {} to {}, transposing by {} octave(s) and {} semitone(s)", name, low, high, shift / 12, shift % 12);
+    }
+    let track_count = song.tracks().count();
+    for index in config.tracks.iter().filter(|&&index| index >= track_count)
+    {
+        eprintln!("Track {} does not exist, song only has {} track(s)", index, track_count);
+    }
+    for (i, track, reason) in song.skipped_tracks(config)
+    {
+        println!("Skipping track {} ({}): {}", i, track.name, reason);
+    }
+}
+
+/// Write charts for a song to a directory of the given name within the output path
+fn output(fingering_chart: &Song, dir_name: &str, config: &Config) -> Result<(), Error>
+{
+    let output_path = format!("{}/{}", config.output_path, dir_name);
+    match config.output_format
+    {
+        OutputFormat::Tracks => fingering_chart.output_entire(&output_path, config),
+        OutputFormat::Rows => fingering_chart.output_rows(&output_path, config),
+        OutputFormat::Separate => fingering_chart.output_cells(&output_path, config),
+        OutputFormat::Pdf => fingering_chart.output_pdf(&output_path, dir_name, config)
+    }
+}
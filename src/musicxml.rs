@@ -0,0 +1,303 @@
+use crate::{Error, note::Spelling, song::{Metadata, RawNote, RawSong, score_channel}};
+use roxmltree::{Document, Node, ParsingOptions};
+use std::{fs, io::{self, Cursor, Read}, path::Path, collections::HashMap};
+use zip::{ZipArchive, result::ZipError};
+
+/// Ticks per beat used for notes read from MusicXML, which divides evenly by the divisions most scores use
+const TICKS_PER_BEAT: u32 = 960;
+/// MusicXML dynamics are a percentage of this velocity
+const FORTE_VELOCITY: f32 = 90.0;
+/// Exported scores start with a doctype, which is skipped rather than rejected
+const PARSE_OPTIONS: ParsingOptions = ParsingOptions { allow_dtd: true, nodes_limit: u32::MAX };
+
+/// Details of a part from the part list of a score
+#[derive(Default)]
+struct PartInfo
+{
+    name: Option<String>,
+    instrument: Option<String>,
+    channel: Option<u8>,
+    program: Option<u8>
+}
+
+/// Read a MusicXML score, either plain or compressed (`.mxl`). Each part becomes a track. Pitches are converted from
+/// written to concert pitch using the transposition of each part, then moved by the given number of semitones.
+pub(crate) fn read(path: &str, transposition: i16) -> Result<RawSong, Error>
+{
+    let is_compressed = Path::new(path).extension().is_some_and(|extension| extension.eq_ignore_ascii_case("mxl"));
+    let text = if is_compressed
+    {
+        unzip_score(&fs::read(path)?)?
+    }
+    else
+    {
+        fs::read_to_string(path)?
+    };
+    parse(&text, transposition)
+}
+
+/// Read the parts of a MusicXML document. Only partwise scores are supported, which is what notation software exports.
+pub(crate) fn parse(text: &str, transposition: i16) -> Result<RawSong, Error>
+{
+    let document = Document::parse_with_options(text, PARSE_OPTIONS)?;
+    let score = document.root_element();
+    if score.tag_name().name() != "score-partwise"
+    {
+        return Err(Error::Score(format!("expected a partwise score, found <{}>", score.tag_name().name())));
+    }
+
+    let mut metadata = Metadata
+    {
+        title: child_text(score, &["work", "work-title"]).or_else(|| child_text(score, &["movement-title"])),
+        copyright: child_text(score, &["identification", "rights"]),
+        text: Vec::new()
+    };
+    if let Some(identification) = child(score, "identification")
+    {
+        metadata.text.extend(identification.children().filter(|node| node.has_tag_name("creator")).filter_map(text_of));
+    }
+
+    let part_info: HashMap<&str, PartInfo> = child(score, "part-list")
+        .into_iter()
+        .flat_map(|list| list.children().filter(|node| node.has_tag_name("score-part")))
+        .map(|part| (part.attribute("id").unwrap_or_default(), PartInfo
+        {
+            name: child_text(part, &["part-name"]),
+            instrument: child_text(part, &["score-instrument", "instrument-name"]),
+            channel: number(part, &["midi-instrument", "midi-channel"]).map(|channel: u8| channel.saturating_sub(1)),
+            program: number(part, &["midi-instrument", "midi-program"]).map(|program: u8| program.saturating_sub(1))
+        }))
+        .collect();
+
+    let mut song = RawSong { ticks_per_beat: TICKS_PER_BEAT, bars: vec![0], metadata, tracks: Vec::new(), names: Vec::new(), key_signatures: Vec::new(), warnings: Vec::new() };
+    for (i, part) in score.children().filter(|node| node.has_tag_name("part")).enumerate()
+    {
+        let info = part.attribute("id").and_then(|id| part_info.get(id));
+        let default_info = PartInfo::default();
+        let info = info.unwrap_or(&default_info);
+
+        // Parts without a midi channel get their own
+        let channel = info.channel.unwrap_or_else(|| score_channel(i));
+        let (notes, bars) = read_part(part, channel, info.program, transposition)?;
+        if i == 0
+        {
+            song.bars = bars;
+        }
+        song.tracks.push(notes);
+        song.names.push((info.name.clone(), info.instrument.clone()));
+    }
+    Ok(song)
+}
+
+/// Read the notes of a single part along with the tick each of its measures starts on. Tied notes are joined into a
+/// single note, and grace notes, cue notes and unpitched notes are left out.
+fn read_part(part: Node, channel: u8, program: Option<u8>, transposition: i16) -> Result<(Vec<RawNote>, Vec<u32>), Error>
+{
+    let mut notes: Vec<RawNote> = Vec::new();
+    let mut bars: Vec<u32> = Vec::new();
+
+    // Notes waiting for the note they are tied to, keyed by concert pitch
+    let mut ties: HashMap<i16, usize> = HashMap::new();
+    let mut divisions: u32 = 1;
+    let mut chromatic: i16 = 0;
+    let mut velocity = FORTE_VELOCITY as u8;
+    let mut tick: u32 = 0;
+    let mut chord_start: u32 = 0;
+    for measure in part.children().filter(|node| node.has_tag_name("measure"))
+    {
+        // Voices move back and forth within a measure, the next measure starts after the furthest any voice reached
+        bars.push(tick);
+        let mut measure_end = tick;
+        for element in measure.children().filter(Node::is_element)
+        {
+            let ticks = |node: Node| number::<f32>(node, &["duration"])
+                .map_or(0, |duration| (duration * TICKS_PER_BEAT as f32 / divisions as f32).round() as u32);
+            match element.tag_name().name()
+            {
+                "attributes" =>
+                {
+                    if let Some(value) = number(element, &["divisions"])
+                    {
+                        divisions = f32::max(value, 1.0) as u32;
+                    }
+                    if let Some(transpose) = child(element, "transpose")
+                    {
+                        chromatic = number(transpose, &["octave-change"]).unwrap_or(0i16)
+                            .checked_mul(12)
+                            .and_then(|octaves| octaves.checked_add(number(transpose, &["chromatic"]).unwrap_or(0.0f32) as i16))
+                            .ok_or_else(|| Error::Score(String::from("transposition is too large")))?;
+                    }
+                },
+                "backup" => tick = tick.saturating_sub(ticks(element)),
+                "forward" => tick = advance(tick, ticks(element))?,
+                "direction" | "sound" =>
+                {
+                    let sound = if element.has_tag_name("sound") { Some(element) } else { child(element, "sound") };
+                    if let Some(dynamics) = sound.and_then(|sound| sound.attribute("dynamics")).and_then(|value| value.parse::<f32>().ok())
+                    {
+                        velocity = dynamics_velocity(dynamics);
+                    }
+                },
+                "note" =>
+                {
+                    if child(element, "grace").is_some() || child(element, "cue").is_some()
+                    {
+                        continue;
+                    }
+                    let duration = ticks(element);
+                    let chord = child(element, "chord").is_some();
+                    let start = if chord { chord_start } else { tick };
+                    let end = advance(start, duration)?;
+                    if !chord
+                    {
+                        chord_start = tick;
+                        tick = end;
+                    }
+                    measure_end = measure_end.max(tick);
+
+                    let pitch = match child(element, "pitch")
+                    {
+                        Some(pitch) => pitch,
+                        None => continue
+                    };
+                    let (written, spelling) = read_pitch(pitch)?;
+                    let (concert, key) = written.checked_add(chromatic)
+                        .and_then(|concert| Some((concert, concert.checked_add(transposition)?)))
+                        .ok_or_else(|| Error::Score(format!("pitch {} is out of range", written)))?;
+                    let spelling = spelling.and_then(|spelling| spelling.transposed(written, key));
+                    let tie = |kind: &str| element.children().any(|node| node.has_tag_name("tie") && node.attribute("type") == Some(kind));
+
+                    // The second half of a tie lengthens the note it is tied to instead of starting a new note. A tie which 
+                    // would end before the note it's tied to has started, after a backup, starts a new note instead.
+                    if tie("stop")
+                    {
+                        if let Some(i) = ties.remove(&concert)
+                        {
+                            if let Some(duration) = end.checked_sub(notes[i].start).filter(|&duration| duration > 0)
+                            {
+                                notes[i].duration = duration;
+                                if tie("start")
+                                {
+                                    ties.insert(concert, i);
+                                }
+                                continue;
+                            }
+                        }
+                    }
+                    if tie("start")
+                    {
+                        ties.insert(concert, notes.len());
+                    }
+                    let velocity = element
+                        .attribute("dynamics")
+                        .and_then(|value| value.parse::<f32>().ok())
+                        .map_or(velocity, dynamics_velocity);
+                    notes.push(RawNote { key, velocity, channel, program, start, duration, spelling, fingering: None });
+                },
+                _ => ()
+            }
+            measure_end = measure_end.max(tick);
+        }
+        tick = measure_end;
+    }
+
+    // Voices are written one after another within each measure, so notes need to be put back in time order
+    notes.sort_by_key(|note| note.start);
+    if bars.is_empty()
+    {
+        bars.push(0);
+    }
+    Ok((notes, bars))
+}
+
+/// Read a written pitch as a midi key along with how it is spelled. Microtones are rounded to the nearest semitone.
+fn read_pitch(pitch: Node) -> Result<(i16, Option<Spelling>), Error>
+{
+    let step = child_text(pitch, &["step"]).and_then(|step| step.chars().next()).unwrap_or_default();
+    let natural = Spelling::natural(step).ok_or_else(|| Error::Score(format!("invalid pitch step '{}'", step)))?;
+    let alter = number(pitch, &["alter"]).unwrap_or(0.0f32).round() as i16;
+    let octave: i16 = number(pitch, &["octave"]).ok_or_else(|| Error::Score(String::from("pitch has no octave")))?;
+    if !(0..=9).contains(&octave)
+    {
+        return Err(Error::Score(format!("invalid pitch octave {}", octave)));
+    }
+    let key = ((octave + 1) * 12 + natural).checked_add(alter).ok_or_else(|| Error::Score(format!("invalid pitch alter {}", alter)))?;
+    Ok((key, Spelling::with_letter(key, step)))
+}
+
+/// Move a tick forward by a duration, failing if the part is too long to count in ticks
+fn advance(tick: u32, duration: u32) -> Result<u32, Error>
+{
+    tick.checked_add(duration).ok_or_else(|| Error::Score(String::from("part is too long")))
+}
+
+/// Convert MusicXML dynamics, a percentage of forte, to a midi velocity
+fn dynamics_velocity(dynamics: f32) -> u8
+{
+    (dynamics / 100.0 * FORTE_VELOCITY).round().clamp(1.0, 127.0) as u8
+}
+
+/// Find the first child element with the given name
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>>
+{
+    node.children().find(|child| child.has_tag_name(name))
+}
+
+/// Follow a path of child elements and read the trimmed text of the last one, if it has any
+fn child_text(node: Node, path: &[&str]) -> Option<String>
+{
+    path.iter().try_fold(node, |node, name| child(node, name)).and_then(text_of)
+}
+
+/// Follow a path of child elements and parse the text of the last one as a number
+fn number<T: std::str::FromStr>(node: Node, path: &[&str]) -> Option<T>
+{
+    child_text(node, path).and_then(|text| text.parse().ok())
+}
+
+/// The trimmed text of an element, if it has any
+fn text_of(node: Node) -> Option<String>
+{
+    node.text().map(str::trim).filter(|text| !text.is_empty()).map(str::to_string)
+}
+
+/// Extract the score from a compressed MusicXML archive, which is a zip file. The score's location is listed in
+/// `META-INF/container.xml`, or failing that it is the first xml file outside of `META-INF`.
+fn unzip_score(data: &[u8]) -> Result<String, Error>
+{
+    let mut archive = ZipArchive::new(Cursor::new(data))?;
+    let root = match archive.by_name("META-INF/container.xml")
+    {
+        Ok(container) =>
+        {
+            let container = read_entry(container)?;
+            let document = Document::parse_with_options(&container, PARSE_OPTIONS)?;
+            document
+                .descendants()
+                .find(|node| node.has_tag_name("rootfile"))
+                .and_then(|node| node.attribute("full-path"))
+                .map(str::to_string)
+        },
+        Err(ZipError::FileNotFound) => None,
+        Err(e) => return Err(e.into())
+    };
+    let root = root.filter(|path| archive.index_for_name(path).is_some()).or_else(|| archive
+        .file_names()
+        .find(|file| !file.starts_with("META-INF/") && (file.ends_with(".xml") || file.ends_with(".musicxml")))
+        .map(str::to_string));
+    let root = root.ok_or_else(|| Error::Score(String::from("no score found in compressed MusicXML archive")))?;
+    let score = read_entry(archive.by_name(&root)?)?;
+    Ok(score)
+}
+
+/// Read a file from a zip archive as text
+fn read_entry(mut entry: impl Read) -> Result<String, Error>
+{
+    let mut text = String::new();
+    entry.read_to_string(&mut text).map_err(|e| match e.kind()
+    {
+        io::ErrorKind::InvalidData => Error::Score(String::from("score is not valid UTF-8")),
+        _ => Error::Io(e)
+    })?;
+    Ok(text)
+}
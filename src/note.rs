@@ -1,10 +1,7 @@
 use enumset::EnumSet;
-use std::collections::HashMap;
-use crate::keys::Key;
-use crate::CONFIG;
-
-/// Type alias for note constants
-pub type NoteConst = &'static Note;
+use serde::{Deserialize, Deserializer, de::{Visitor, SeqAccess, MapAccess, value::{SeqAccessDeserializer, MapAccessDeserializer}}};
+use std::{fmt, convert::TryFrom, str::FromStr, sync::Arc, collections::HashMap};
+use crate::{Config, Error, keys::Key, theme::Theme};
 
 /// Struct used for individual notes
 pub struct Note
@@ -25,9 +22,63 @@ impl PartialEq for Note
 pub struct Fingering
 {
     pub keys: EnumSet<Key>,
+    pub preferred: bool,
+    /// A short name for the fingering, such as "bis", used to tell alternate fingerings apart
+    pub name: Option<String>,
     pub image: image::DynamicImage
 }
 
+/// A fingering as written in cfg.ron. Either a plain list of keys, or a struct with a `keys` list and
+/// other optional fields.
+pub struct FingeringConfig
+{
+    pub keys: EnumSet<Key>,
+    pub preferred: bool,
+    pub name: Option<String>
+}
+
+impl<'de> Deserialize<'de> for FingeringConfig
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error>
+    {
+        /// The struct form of a fingering
+        #[derive(Deserialize)]
+        struct Detailed
+        {
+            keys: EnumSet<Key>,
+            #[serde(default)]
+            preferred: bool,
+            #[serde(default, deserialize_with = "crate::layer::some")]
+            name: Option<String>
+        }
+
+        struct FingeringVisitor;
+        impl<'de> Visitor<'de> for FingeringVisitor
+        {
+            type Value = FingeringConfig;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result
+            {
+                formatter.write_str("a list of keys or a fingering struct")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<FingeringConfig, A::Error>
+            {
+                let keys = EnumSet::deserialize(SeqAccessDeserializer::new(seq))?;
+                Ok(FingeringConfig { keys, preferred: false, name: None })
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<FingeringConfig, A::Error>
+            {
+                let Detailed { keys, preferred, name } = Detailed::deserialize(MapAccessDeserializer::new(map))?;
+                Ok(FingeringConfig { keys, preferred, name })
+            }
+        }
+
+        deserializer.deserialize_any(FingeringVisitor)
+    }
+}
+
 impl PartialEq for Fingering
 {
     fn eq(&self, other: &Self) -> bool 
@@ -36,62 +87,306 @@ impl PartialEq for Fingering
     }
 }
 
-// Define notes and load note images using lazy_static
-lazy_static!
+/// The notes defined in a config along with their fingering charts, keyed by midi byte
+pub struct Notes(HashMap<u8, Arc<Note>>);
+
+impl Notes
+{
+    /// Load the notes defined in a config and generate their fingering charts, checking the rest of the config can be
+    /// drawn with
+    pub fn new(config: &Config) -> Result<Notes, Error>
+    {
+        if config.notes_per_row == 0
+        {
+            return Err(Error::NoNotesPerRow);
+        }
+        let theme = config.theme.resolve();
+        let mut notes: HashMap<u8, Arc<Note>> = HashMap::new();
+        for (byte, fingerings) in &config.notes
+        {
+            if fingerings.is_empty()
+            {
+                return Err(Error::NoFingerings(*byte));
+            }
+            let fingerings = fingerings
+                .iter()
+                .map(|fingering| Fingering::new(fingering, *byte, config, &theme))
+                .collect();
+            notes.insert(*byte, Arc::new(Note { byte: *byte, fingerings }));
+        }
+        Ok(Notes(notes))
+    }
+
+    /// Access a note via it's midi byte index.
+    pub fn get(&self, byte: u8) -> Option<&Arc<Note>>
+    {
+        self.0.get(&byte)
+    }
+
+    /// Iterate over every note, from lowest to highest
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<Note>>
+    {
+        let mut notes: Vec<&Arc<Note>> = self.0.values().collect();
+        notes.sort_by_key(|note| note.byte);
+        notes.into_iter()
+    }
+}
+
+impl Fingering
+{
+    /// Fingering contructor, the chart is drawn as set in the config
+    pub fn new(fingering: &FingeringConfig, byte: u8, config: &Config, theme: &Theme) -> Fingering
+    {
+        let mut image = Fingering::gen_chart(fingering.keys, theme);
+        Note::include_note_name(byte, Note::spelling(byte), config, theme, &mut image);
+        Fingering { keys: fingering.keys, preferred: fingering.preferred, name: fingering.name.clone(), image }
+    }
+
+    /// The chart for this fingering with the note name spelled as given. The chart generated at load-time is 
+    /// reused unless the spelling differs from the note's default.
+    pub fn spelled_image(&self, byte: u8, spelling: Option<Spelling>, config: &Config, theme: &Theme) -> image::DynamicImage
+    {
+        match spelling
+        {
+            Some(spelling) if spelling != Note::spelling(byte) =>
+            {
+                let mut image = Fingering::gen_chart(self.keys, theme);
+                Note::include_note_name(byte, spelling, config, theme, &mut image);
+                image
+            },
+            _ => self.image.clone()
+        }
+    }
+}
+
+/// The accidental written after a note's letter
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Accidental
+{
+    Natural,
+    Flat,
+    Sharp
+}
+
+/// How the name of a note is written, as a letter from A to G and an accidental
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Spelling
+{
+    pub letter: char,
+    pub accidental: Accidental
+}
+
+impl fmt::Display for Spelling
+{
+    /// Writes the letter followed by `b` for a flat or `#` for a sharp
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        let accidental = match self.accidental
+        {
+            Accidental::Natural => "",
+            Accidental::Flat => "b",
+            Accidental::Sharp => "#"
+        };
+        write!(f, "{}{}", self.letter, accidental)
+    }
+}
+
+/// Note letters in order, starting from C
+pub(crate) const LETTERS: [char; 7] = ['C', 'D', 'E', 'F', 'G', 'A', 'B'];
+/// The pitch class of each natural note, in the same order as the letters
+const NATURALS: [i16; 7] = [0, 2, 4, 5, 7, 9, 11];
+/// The number of letter steps spanned by an interval of each number of semitones within an octave
+const INTERVAL_STEPS: [i16; 12] = [0, 1, 1, 2, 2, 3, 3, 4, 5, 5, 6, 6];
+
+impl Spelling
+{
+    /// The pitch class of a natural note letter, ignoring case
+    pub fn natural(letter: char) -> Option<i16>
+    {
+        let index = LETTERS.iter().position(|&l| l == letter.to_ascii_uppercase())?;
+        Some(NATURALS[index])
+    }
+
+    /// Spell a pitch using the given letter, as long as it needs no more than a single flat or sharp
+    pub fn with_letter(key: i16, letter: char) -> Option<Spelling>
+    {
+        let index = LETTERS.iter().position(|&l| l == letter.to_ascii_uppercase())?;
+        let accidental = match (key.rem_euclid(12) - NATURALS[index] + 6).rem_euclid(12) - 6
+        {
+            -1 => Accidental::Flat,
+            0 => Accidental::Natural,
+            1 => Accidental::Sharp,
+            _ => return None
+        };
+        Some(Spelling { letter: LETTERS[index], accidental })
+    }
+
+    /// Move this spelling along with its note from one key to another. The letter moves by the number of steps 
+    /// that matches the interval, so a B flat moved up a major second becomes a C rather than a B sharp.
+    pub fn transposed(self, from: i16, to: i16) -> Option<Spelling>
+    {
+        let interval = to - from;
+        let steps = interval.div_euclid(12) * 7 + INTERVAL_STEPS[interval.rem_euclid(12) as usize];
+        let index = LETTERS.iter().position(|&l| l == self.letter)? as i16;
+        Spelling::with_letter(to, LETTERS[(index + steps).rem_euclid(7) as usize])
+    }
+}
+
+/// Note letters in the order sharps are added to a key signature, starting from F which is flat in the key of F
+pub(crate) const SHARP_ORDER: [char; 7] = ['F', 'C', 'G', 'D', 'A', 'E', 'B'];
+
+/// A key signature, as the number of sharps or a negative number of flats, and whether the key is minor. Notes in the 
+/// key are spelled with its letters, and other notes use sharps in sharp keys and flats otherwise.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default, Deserialize)]
+#[serde(try_from = "String")]
+pub struct KeySignature
+{
+    pub sharps: i8,
+    pub minor: bool
+}
+
+impl KeySignature
 {
-    /// Map of loaded note constants
-    pub static ref NOTES: HashMap<u8, Note> = 
+    /// The pitch class and letter index of the major key's tonic
+    fn major_tonic(self) -> (i16, usize)
+    {
+        let sharps = self.sharps as i16;
+        ((sharps * 7).rem_euclid(12), (sharps * 4).rem_euclid(7) as usize)
+    }
+
+    /// The tonic of the key, such as `Bb` or `F#`
+    pub fn tonic(self) -> Spelling
+    {
+        let (pitch, letter) = self.major_tonic();
+        let (pitch, letter) = if self.minor { (pitch + 9, (letter + 5) % 7) } else { (pitch, letter) };
+        Spelling::with_letter(pitch, LETTERS[letter]).unwrap_or(Spelling { letter: LETTERS[letter], accidental: Accidental::Natural })
+    }
+
+    /// Spell a midi key in this key signature. The raised seventh of a minor key is spelled as a sharp of its letter.
+    pub fn spell(self, key: i16) -> Spelling
     {
-        match &*CONFIG
+        let (tonic, tonic_letter) = self.major_tonic();
+        let offset = (key - tonic).rem_euclid(12);
+        let letter = match NATURALS.iter().position(|&natural| natural == offset)
+        {
+            Some(degree) => Some(LETTERS[(tonic_letter + degree) % 7]),
+            None if self.minor && offset == 8 => Some(LETTERS[(tonic_letter + 4) % 7]),
+            None => None
+        };
+        if let Some(spelling) = letter.and_then(|letter| Spelling::with_letter(key, letter))
+        {
+            return spelling;
+        }
+
+        // Notes outside of the key are naturals where possible, otherwise sharps in sharp keys and flats in others
+        let spelling = Note::spelling(key.rem_euclid(12) as u8);
+        match LETTERS.iter().position(|&l| l == spelling.letter)
         {
-            Ok(config) => 
+            Some(index) if self.sharps > 0 && spelling.accidental == Accidental::Flat =>
             {
-                let mut notes: HashMap<u8, Note> = HashMap::new();
-                for (byte, fingerings) in &config.notes
-                {
-                    let fingerings = fingerings
-                        .into_iter()
-                        .map(|&enumset| Fingering::new(enumset, *byte))
-                        .collect();
-                    match notes.get_mut(&byte)
-                    {
-                        None => 
-                        {
-                            notes.insert(*byte, Note { byte: *byte, fingerings });
-                        },
-                        Some(note) => 
-                        {
-                            note.fingerings.extend(fingerings);
-                        }
-                    };
-                }
-                notes
+                Spelling { letter: LETTERS[(index + 6) % 7], accidental: Accidental::Sharp }
             },
-            Err(e) => 
+            _ => spelling
+        }
+    }
+
+    /// The key signature of a key moved by the given number of semitones. Keys moved by anything other than whole 
+    /// octaves get no more than 6 sharps or flats.
+    pub fn transposed(self, semitones: i16) -> KeySignature
+    {
+        if semitones.rem_euclid(12) == 0
+        {
+            return self;
+        }
+        let sharps = (self.sharps as i16 + semitones * 7).rem_euclid(12);
+        KeySignature { sharps: if sharps > 6 { sharps - 12 } else { sharps } as i8, ..self }
+    }
+
+    /// Guess the key of a piece from its notes, given as midi keys along with how long each lasts. The key signature 
+    /// whose scale covers the most of the piece is chosen, preferring fewer sharps or flats. The key is minor if the 
+    /// tonic of the relative minor is heard more than the tonic of the major.
+    pub fn detect(notes: impl Iterator<Item = (i16, u32)>) -> KeySignature
+    {
+        let mut weights = [0u64; 12];
+        for (key, duration) in notes
+        {
+            weights[key.rem_euclid(12) as usize] += duration.max(1) as u64;
+        }
+        let mut best = KeySignature::default();
+        let mut best_weight = 0;
+        for sharps in std::iter::once(0).chain((1..=6).flat_map(|sharps| [-sharps, sharps]))
+        {
+            let key = KeySignature { sharps, minor: false };
+            let (tonic, _) = key.major_tonic();
+            let weight: u64 = NATURALS.iter().map(|natural| weights[((tonic + natural) % 12) as usize]).sum();
+            if weight > best_weight
             {
-                eprintln!("Failed to load config: {}", e);
-                HashMap::new()
+                best = key;
+                best_weight = weight;
             }
         }
-    };
+        let (tonic, _) = best.major_tonic();
+        best.minor = weights[((tonic + 9) % 12) as usize] > weights[tonic as usize];
+        best
+    }
 }
 
-impl Note
+impl fmt::Display for KeySignature
 {
-    /// Access a note via it's midi byte index.
-    pub fn get(byte: u8) -> Option<NoteConst>
+    /// Writes the tonic followed by `major` or `minor`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
     {
-        NOTES.get(&byte)
+        write!(f, "{} {}", self.tonic(), if self.minor { "minor" } else { "major" })
     }
 }
 
-impl Fingering
+impl FromStr for KeySignature
+{
+    type Err = String;
+
+    /// Read a key such as `Bb`, `Eb major`, `F#m` or `C# minor`. Keys are major unless they say otherwise.
+    fn from_str(text: &str) -> Result<KeySignature, String>
+    {
+        let invalid = || format!("invalid key '{}'", text);
+        let mut chars = text.trim().chars();
+        let letter = chars.next().ok_or_else(invalid)?.to_ascii_uppercase();
+        let mut sharps = SHARP_ORDER.iter().position(|&l| l == letter).ok_or_else(invalid)? as i8 - 1;
+        let too_many = || format!("key '{}' has more than 7 sharps or flats", text);
+        let mut rest = chars.as_str();
+        while let Some(c) = chars.next()
+        {
+            match c
+            {
+                'b' | '\u{266D}' => sharps = sharps.checked_sub(7).ok_or_else(too_many)?,
+                '#' | '\u{266F}' => sharps = sharps.checked_add(7).ok_or_else(too_many)?,
+                _ => break
+            }
+            rest = chars.as_str();
+        }
+        let minor = match rest.trim().to_lowercase().as_str()
+        {
+            "" | "maj" | "major" => false,
+            "m" | "min" | "minor" => true,
+            _ => return Err(invalid())
+        };
+        if minor
+        {
+            sharps = sharps.checked_sub(3).ok_or_else(too_many)?;
+        }
+        if !(-7..=7).contains(&sharps)
+        {
+            return Err(too_many());
+        }
+        Ok(KeySignature { sharps, minor })
+    }
+}
+
+impl TryFrom<String> for KeySignature
 {
-    /// Fingering contructor
-    pub fn new(keys: EnumSet<Key>, byte: u8) -> Fingering
+    type Error = String;
+
+    fn try_from(text: String) -> Result<KeySignature, String>
     {
-        let mut image = Fingering::gen_chart(keys);
-        Note::include_note_name(byte, &mut image);
-        Fingering { keys, image }
+        text.parse()
     }
-}
\ No newline at end of file
+}
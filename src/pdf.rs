@@ -0,0 +1,210 @@
+use image::DynamicImage;
+use miniz_oxide::deflate::{compress_to_vec_zlib, CompressionLevel};
+use pdf_writer::{Content, Filter, Finish, Name, Pdf, Rect, Ref, Str};
+use serde::Deserialize;
+use std::{fs, io};
+
+/// Font size of the page header, in points
+const HEADER_SIZE: f32 = 12.0;
+/// Font size of page numbers, in points
+const FOOTER_SIZE: f32 = 9.0;
+/// Space between the header or footer text and the charts, in points
+const TEXT_GAP: f32 = 8.0;
+/// Charts are never drawn larger than this many points per pixel
+const MAX_SCALE: f32 = 1.0;
+
+/// The paper size of pdf pages
+#[derive(Copy, Clone, Deserialize, PartialEq, Default)]
+pub enum PageSize
+{
+    #[default]
+    A4,
+    Letter
+}
+
+impl PageSize
+{
+    /// Width and height in points
+    fn dimensions(self) -> (f32, f32)
+    {
+        match self
+        {
+            PageSize::A4 => (595.0, 842.0),
+            PageSize::Letter => (612.0, 792.0)
+        }
+    }
+}
+
+/// Settings for pdf output from the cfg.ron file
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct PdfConfig
+{
+    pub page_size: PageSize,
+    /// Page margin, in points
+    pub margin: f32,
+    /// Whether every track goes into a single document
+    pub single_document: bool
+}
+
+impl Default for PdfConfig
+{
+    fn default() -> Self
+    {
+        PdfConfig { page_size: PageSize::A4, margin: 36.0, single_document: false }
+    }
+}
+
+/// A single track to be written into a pdf, as rows of charts
+pub struct PdfTrack
+{
+    pub name: String,
+    pub rows: Vec<DynamicImage>
+}
+
+/// A row placed on a page, as an index into the rows of a track and its position in points
+struct Placement
+{
+    track: usize,
+    row: usize,
+    x: f32,
+    y: f32,
+    width: f32,
+    height: f32
+}
+
+/// A page of placed rows, along with the track the page belongs to
+struct Page
+{
+    track: usize,
+    rows: Vec<Placement>
+}
+
+/// Write rows of charts to a pdf. Rows are scaled to fit the page width and never split across pages.
+/// Each track starts on a new page, and every page has a header with the title and track name, and a page number.
+pub fn write_pdf(path: &str, title: &str, tracks: &[PdfTrack], spacing: usize, config: &PdfConfig) -> io::Result<()>
+{
+    let pages = paginate(tracks, spacing, config);
+    let (page_width, page_height) = config.page_size.dimensions();
+
+    // Reference ids. Each page takes up 2 ids, one for the page itself and one for its content. Rows take 1 each.
+    let catalog_id = Ref::new(1);
+    let page_tree_id = Ref::new(2);
+    let font_id = Ref::new(3);
+    let font_name = Name(b"F1");
+    let page_ids: Vec<(Ref, Ref)> = (0..pages.len() as i32)
+        .map(|i| (Ref::new(4 + i * 2), Ref::new(5 + i * 2)))
+        .collect();
+    let first_image_id = 4 + pages.len() as i32 * 2;
+    let image_ids: Vec<Vec<Ref>> = tracks
+        .iter()
+        .scan(first_image_id, |next, track|
+        {
+            let ids = (0..track.rows.len() as i32).map(|i| Ref::new(*next + i)).collect();
+            *next += track.rows.len() as i32;
+            Some(ids)
+        })
+        .collect();
+    let image_name = |track: usize, row: usize| format!("Im{}_{}", track, row);
+
+    let mut pdf = Pdf::new();
+    pdf.catalog(catalog_id).pages(page_tree_id);
+    pdf.pages(page_tree_id).kids(page_ids.iter().map(|&(page_id, _)| page_id)).count(pages.len() as i32);
+    pdf.type1_font(font_id).base_font(Name(b"Helvetica"));
+
+    for (number, (page, &(page_id, content_id))) in pages.iter().zip(&page_ids).enumerate()
+    {
+        let mut page_writer = pdf.page(page_id);
+        page_writer.media_box(Rect::new(0.0, 0.0, page_width, page_height));
+        page_writer.parent(page_tree_id);
+        page_writer.contents(content_id);
+        let mut resources = page_writer.resources();
+        resources.fonts().pair(font_name, font_id);
+        let mut x_objects = resources.x_objects();
+        for placement in &page.rows
+        {
+            x_objects.pair(Name(image_name(placement.track, placement.row).as_bytes()), image_ids[placement.track][placement.row]);
+        }
+        x_objects.finish();
+        resources.finish();
+        page_writer.finish();
+
+        let mut content = Content::new();
+        for placement in &page.rows
+        {
+            content.save_state();
+            content.transform([placement.width, 0.0, 0.0, placement.height, placement.x, placement.y]);
+            content.x_object(Name(image_name(placement.track, placement.row).as_bytes()));
+            content.restore_state();
+        }
+        let name = &tracks[page.track].name;
+        let header = if name == title { name.clone() } else { format!("{} - {}", title, name) };
+        let footer = format!("Page {} of {}", number + 1, pages.len());
+        content.begin_text()
+            .set_font(font_name, HEADER_SIZE)
+            .next_line(config.margin, page_height - config.margin - HEADER_SIZE)
+            .show(Str(pdf_text(&header).as_bytes()))
+            .end_text();
+        content.begin_text()
+            .set_font(font_name, FOOTER_SIZE)
+            .next_line(page_width / 2.0 - FOOTER_SIZE * footer.len() as f32 / 4.0, config.margin)
+            .show(Str(pdf_text(&footer).as_bytes()))
+            .end_text();
+        pdf.stream(content_id, &content.finish());
+    }
+
+    // Embed every row as a compressed image
+    for (track, ids) in tracks.iter().zip(&image_ids)
+    {
+        for (row, &id) in track.rows.iter().zip(ids)
+        {
+            let data = compress_to_vec_zlib(row.to_rgb8().as_raw(), CompressionLevel::DefaultLevel as u8);
+            let mut image = pdf.image_xobject(id, &data);
+            image.filter(Filter::FlateDecode);
+            image.width(row.width() as i32);
+            image.height(row.height() as i32);
+            image.color_space().device_rgb();
+            image.bits_per_component(8);
+            image.interpolate(false);
+        }
+    }
+
+    fs::write(path, pdf.finish())
+}
+
+/// Place rows onto pages. All rows of a track share the same scale, chosen so the widest row fits the page.
+fn paginate(tracks: &[PdfTrack], spacing: usize, config: &PdfConfig) -> Vec<Page>
+{
+    let (page_width, page_height) = config.page_size.dimensions();
+    let content_width = page_width - config.margin * 2.0;
+    let top = page_height - config.margin - HEADER_SIZE - TEXT_GAP;
+    let bottom = config.margin + FOOTER_SIZE + TEXT_GAP;
+
+    let mut pages = Vec::new();
+    for (track_index, track) in tracks.iter().enumerate()
+    {
+        let widest = track.rows.iter().map(|row| row.width()).max().unwrap_or(1).max(1);
+        let scale = (content_width / widest as f32).min(MAX_SCALE);
+        let mut page = Page { track: track_index, rows: Vec::new() };
+        let mut y = top;
+        for (row_index, row) in track.rows.iter().enumerate()
+        {
+            let (width, height) = (row.width() as f32 * scale, row.height() as f32 * scale);
+            if y - height < bottom && !page.rows.is_empty()
+            {
+                pages.push(std::mem::replace(&mut page, Page { track: track_index, rows: Vec::new() }));
+                y = top;
+            }
+            page.rows.push(Placement { track: track_index, row: row_index, x: config.margin, y: y - height, width, height });
+            y -= height + spacing as f32 * scale;
+        }
+        pages.push(page);
+    }
+    pages
+}
+
+/// Pdf standard fonts only support a limited character set, anything outside of ascii is replaced
+fn pdf_text(text: &str) -> String
+{
+    text.chars().map(|c| if c.is_ascii() && !c.is_ascii_control() { c } else { '?' }).collect()
+}
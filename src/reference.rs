@@ -0,0 +1,68 @@
+use crate::{
+    Config, Error, Note, Notes,
+    keys::{font::{draw_text, text_width, LINE_HEIGHT}, gen_background, with_padding, image_data::CHART_SIZE},
+    pdf::{write_pdf, PdfTrack}
+};
+use image::{imageops, DynamicImage};
+use std::fs;
+
+/// Title of the reference chart
+const TITLE: &str = "Fingering reference";
+
+/// The label for a note's pitch, such as `Bb3`, where `C4` is middle C
+pub fn pitch_label(byte: u8) -> String
+{
+    format!("{}{}", Note::spelling(byte), Note::octave(byte))
+}
+
+/// The label beneath a fingering, which is its index as used to pin fingerings in note lists, followed by its name
+/// if it has one
+pub fn fingering_label(index: usize, name: Option<&str>) -> String
+{
+    match name
+    {
+        Some(name) => format!("{} {}", index, name),
+        None => index.to_string()
+    }
+}
+
+/// Generates a row for every configured note, from lowest to highest. Each row starts with the pitch and is followed
+/// by every fingering of the note side by side, each labeled with its index and name.
+pub fn reference_rows(notes: &Notes, config: &Config) -> Vec<DynamicImage>
+{
+    let spacing = config.spacing as u32;
+    let theme = config.theme.resolve();
+    let (chart_width, chart_height) = (CHART_SIZE.0 as u32 + theme.padding * 2, CHART_SIZE.1 as u32 + theme.padding * 2);
+    let label_width = notes.iter().map(|note| text_width(&pitch_label(note.byte))).max().unwrap_or(0);
+    notes.iter()
+        .map(|note|
+        {
+            let labels: Vec<String> = note.fingerings
+                .iter()
+                .enumerate()
+                .map(|(i, fingering)| fingering_label(i, fingering.name.as_deref()))
+                .collect();
+            let widths: Vec<u32> = labels.iter().map(|label| text_width(label).max(chart_width)).collect();
+            let width = spacing + label_width + widths.iter().map(|width| spacing + width).sum::<u32>() + spacing;
+            let mut row_image = gen_background(width, chart_height + LINE_HEIGHT, &theme);
+            draw_text(&mut row_image, &pitch_label(note.byte), spacing, (chart_height - LINE_HEIGHT) / 2, theme.font.rgba());
+            let mut x = spacing + label_width + spacing;
+            for ((fingering, label), width) in note.fingerings.iter().zip(&labels).zip(&widths)
+            {
+                imageops::replace(&mut row_image, &with_padding(&fingering.image, &theme), x as i64, 0);
+                draw_text(&mut row_image, label, x, chart_height + 1, theme.font.rgba());
+                x += width + spacing;
+            }
+            row_image
+        })
+        .collect()
+}
+
+/// Writes a pdf reference chart of every configured note and its fingerings to the given directory
+pub fn output_reference(notes: &Notes, output_path: &str, config: &Config) -> Result<(), Error>
+{
+    fs::create_dir_all(output_path)?;
+    let track = PdfTrack { name: TITLE.into(), rows: reference_rows(notes, config) };
+    write_pdf(&format!("{}/reference.pdf", output_path), TITLE, &[track], config.spacing, &config.pdf)?;
+    Ok(())
+}
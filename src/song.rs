@@ -1,129 +1,885 @@
-use crate::{track::*, note::{Note, NoteConst}};
-use std::{fs, collections::HashSet};
-use midly::{Smf, TrackEventKind::*, MidiMessage::*};
-use image::{error::ImageError, imageops::FilterType};
+use crate::{Config, Error, FileType, OutputFormat, Size, KeySignatures, SplitBy, RepeatedNotes, Polyphony, musicxml, abc, text, exercise::Exercise, instrument::{instrument_name, PERCUSSION_CHANNEL}, track::*, keys::scaled, note::{KeySignature, Note, Notes, Spelling}, pdf::{PdfTrack, write_pdf}};
+use std::{fs, cmp::Reverse, convert::TryFrom, path::Path, sync::Arc, collections::{BTreeMap, HashSet, HashMap}};
+use midly::{Smf, Format, Timing, TrackEvent, TrackEventKind::{self, *}, MetaMessage::{self, Tempo, TimeSignature}, MidiMessage::*};
 
-/// Entire song, just a vec of tracks with some methods
-pub struct Song(Vec<Track>);
+/// Tempo assumed when a midi file doesn't specify one, in microseconds per beat (120 bpm)
+const DEFAULT_TEMPO: u32 = 500_000;
+/// Notes which start within this fraction of a beat of each other are treated as a single chord
+const CHORD_FRACTION: u32 = 32;
+
+/// A note read from a file, before it has been matched against the notes in the config
+pub(crate) struct RawNote
+{
+    pub key: i16,
+    pub velocity: u8,
+    pub channel: u8,
+    /// The program the note's channel was set to when it started, if any
+    pub program: Option<u8>,
+    pub start: u32,
+    pub duration: u32,
+    /// How the note's name is written in the file, for files that spell out notes
+    pub spelling: Option<Spelling>,
+    /// The index of the fingering the file asks for, for files that pin fingerings
+    pub fingering: Option<usize>
+}
+
+/// A song read from a file, before its notes have been split into tracks and matched against the notes in the config
+pub(crate) struct RawSong
+{
+    pub ticks_per_beat: u32,
+    /// The tick each measure starts on up to the end of the song, the first measure always starts on tick 0
+    pub bars: Vec<u32>,
+    pub metadata: Metadata,
+    /// The notes of each track in the file
+    pub tracks: Vec<Vec<RawNote>>,
+    /// The name of each track in the file and of the instrument it is played on, if known
+    pub names: Vec<(Option<String>, Option<String>)>,
+    /// The tick each key signature in the file starts on, transposed along with the notes
+    pub key_signatures: Vec<(u32, KeySignature)>,
+    /// Problems found while reading the file which didn't stop it from being read
+    pub warnings: Vec<String>
+}
+
+/// The midi channel for the track at the given position in a score, which doesn't give channels of its own. Each 
+/// track gets its own channel, skipping the percussion channel so that no track is skipped as percussion.
+pub(crate) fn score_channel(index: usize) -> u8
+{
+    match (index % 15) as u8
+    {
+        channel if channel >= PERCUSSION_CHANNEL => channel + 1,
+        channel => channel
+    }
+}
+
+/// A track read from a file, before its notes have been matched against the notes in the config
+struct RawTrack
+{
+    name: String,
+    instrument: Option<String>,
+    notes: Vec<RawNote>
+}
+
+/// Descriptive text about a song, read from the meta events of a midi file or the header of a score
+#[derive(Default)]
+pub struct Metadata
+{
+    /// The name of the song. In midi files this is the name of the first track, unless the file is made of 
+    /// independent sequences.
+    pub title: Option<String>,
+    pub copyright: Option<String>,
+    /// Any other text about the song, such as the composer or arranger. In midi files this is text from the first track.
+    pub text: Vec<String>
+}
+
+/// The kinds of file songs can be loaded from
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum InputFormat
+{
+    Midi,
+    MusicXml,
+    Abc,
+    Text
+}
+
+impl InputFormat
+{
+    /// Determine the format of a file from its extension, ignoring case. Files with other extensions are read as midi,
+    /// and standard input (`-`) is read as a note list.
+    pub fn from_path(path: &str) -> InputFormat
+    {
+        if path == "-"
+        {
+            return InputFormat::Text;
+        }
+        let extension = Path::new(path).extension().map(|extension| extension.to_string_lossy().to_lowercase());
+        match extension.as_deref()
+        {
+            Some("musicxml") | Some("xml") | Some("mxl") => InputFormat::MusicXml,
+            Some("abc") => InputFormat::Abc,
+            Some("txt") => InputFormat::Text,
+            _ => InputFormat::Midi
+        }
+    }
+}
+
+/// What was done to a song's notes while it was loaded, for the caller to report however suits it
+#[derive(Default, Debug)]
+pub struct LoadReport
+{
+    /// The number of notes in chords which were reduced to a single note, or split into voices
+    pub reduced_notes: usize,
+    /// The number of tracks added for the extra voices of chords, when chords are split
+    pub extra_voices: usize,
+    /// How far the song was moved to fit into range, when auto_transpose is set
+    pub transposition: Option<AutoTransposition>,
+    /// The key detected from the notes, when notes are spelled in a detected key
+    pub detected_key: Option<KeySignature>,
+    /// Problems found while loading which didn't stop the song from loading, such as notes out of range
+    pub warnings: Vec<String>
+}
+
+/// The range of a song's notes before auto_transpose, and the number of semitones it was moved by to fit into range
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct AutoTransposition
+{
+    pub low: i16,
+    pub high: i16,
+    pub shift: i16
+}
+
+/// Entire song, a list of tracks along with what its file says about the song
+pub struct Song
+{
+    pub metadata: Arc<Metadata>,
+    /// What was done to the song's notes while it was loaded
+    pub report: LoadReport,
+    tracks: Vec<Track>
+}
 
 impl Song
 {
-    /// Parse a midi file. Generate a list containing all tracks. Tracks themselves are simply lists of notes.
-    pub fn load(midi_path: &str, transposition: i16) -> Song
+    /// Load a song from a midi, MusicXML, ABC or note list file, chosen by the file's extension. Generate a list containing all 
+    /// tracks. Tracks themselves are simply lists of notes. Chords are reduced as set in the config, then notes are 
+    /// matched against the given notes. If auto_transpose is set the song is shifted to best fit their range.
+    pub fn load(path: &str, notes: &Notes, config: &Config) -> Result<Song, Error>
     {
-        // Parse a midi file
-        let raw_data = fs::read(midi_path).expect("Failed to load midi data");
-        let midi = Smf::parse(&raw_data).expect("Failed to parse midi data");
+        let raw = match InputFormat::from_path(path)
+        {
+            InputFormat::Midi => read_midi(path, config)?,
+            InputFormat::MusicXml => musicxml::read(path, config.transposition_type as i16)?,
+            InputFormat::Abc => abc::read(path, config.transposition_type as i16)?,
+            InputFormat::Text => text::read(path, config.transposition_type as i16)?
+        };
+        Ok(Song::from_raw(raw, notes, config))
+    }
 
-        // Keep track of out-of-range notes
-        let mut out_of_range: HashSet<u8> = HashSet::new();
+    /// The name of the directory charts for a song loaded from the given path are written to. ABC tunes are named 
+    /// after their title when they have one, other files after the file name, and standard input is `stdin`.
+    pub fn output_name(&self, path: &str) -> String
+    {
+        match (InputFormat::from_path(path), &self.metadata.title)
+        {
+            (InputFormat::Abc, Some(title)) => file_name(title),
+            _ if path == "-" => String::from("stdin"),
+            _ => Path::new(path).file_stem().unwrap_or_default().to_string_lossy().into_owned()
+        }
+    }
 
-        // Iterate through the midi file and collect notes
-        let mut tracks: Vec<Track> = midi.tracks
-            .iter()
-            .map(|track| track
-                .iter()
-                .filter_map(|event| 
-                    if let Midi { channel: _, message: NoteOn {key, vel: _} } = event.kind 
-                    { 
-                        let true_key = (key.as_int() as i16 + transposition) as u8;
-                        let candidates = Note::get(true_key);
-                        match candidates
-                        {
-                            None => 
-                            {
-                                if out_of_range.insert(true_key)
-                                {
-                                    eprintln!("Note out of range: {}", true_key);
-                                }
-                                None
-                            },
-                            note => note
-                        }
-                    } 
-                    else 
-                    { 
-                        None 
+    /// Generate a song with a single track for a scale or arpeggio exercise, covering the range of the given notes
+    pub fn exercise(exercise: &Exercise, notes: &Notes, config: &Config) -> Song
+    {
+        Song::from_raw(exercise.raw_song(notes), notes, config)
+    }
+
+    /// Split the notes of a song read from a file into tracks, deal with chords, then match the notes against the 
+    /// given notes. What was done along the way is kept in the song's report.
+    pub(crate) fn from_raw(raw: RawSong, notes: &Notes, config: &Config) -> Song
+    {
+        let RawSong { ticks_per_beat, bars: song_bars, metadata, tracks: mut raw_notes, names: track_names, key_signatures, warnings } = raw;
+        let mut report = LoadReport { detected_key: spell_notes(&mut raw_notes, key_signatures, config), warnings, ..LoadReport::default() };
+        let auto_transpose = config.auto_transpose;
+        let raw_tracks: Vec<RawTrack> = split_notes(raw_notes, track_names, config.split_by)
+            .into_iter()
+            .flat_map(|RawTrack { name, instrument, notes: raw_notes }|
+            {
+                let (voices, count) = reduce_polyphony(raw_notes, config.polyphony, ticks_per_beat);
+                report.reduced_notes += count;
+                report.extra_voices += voices.len().saturating_sub(1);
+                voices
+                    .into_iter()
+                    .enumerate()
+                    .map(move |(i, voice)| RawTrack
+                    {
+                        name: if i == 0 { name.clone() } else { format!("{} voice {}", name, i + 1) },
+                        instrument: instrument.clone(),
+                        notes: voice
                     })
-                .collect::<Vec<NoteConst>>())
-            .filter_map(|candidates| 
-                if candidates.is_empty() 
+            })
+            .collect();
+
+        // Determine how far to shift the song to fit it into range
+        let shift = if auto_transpose
+        {
+            let keys: Vec<i16> = raw_tracks.iter().flat_map(|track| &track.notes).map(|note| note.key).collect();
+            let shift = best_shift(&keys, notes);
+            if let (Some(&low), Some(&high)) = (keys.iter().min(), keys.iter().max())
+            {
+                report.transposition = Some(AutoTransposition { low, high, shift });
+            }
+            shift
+        }
+        else
+        {
+            0
+        };
+
+        // Keep track of out-of-range notes
+        let mut out_of_range: HashSet<i16> = HashSet::new();
+
+        // Match notes against the config
+        let metadata = Arc::new(metadata);
+        let mut tracks: Vec<Track> = raw_tracks
+            .into_iter()
+            .filter_map(|RawTrack { name, instrument, notes: raw_notes }|
+            {
+                let mut channels: Vec<u8> = raw_notes.iter().map(|note| note.channel).collect();
+                channels.sort_unstable();
+                channels.dedup();
+                let track_notes = resolve_notes(raw_notes, notes, shift, auto_transpose, &mut out_of_range, &mut report.warnings);
+                if track_notes.is_empty() 
                 { 
                     None 
                 } 
                 else 
                 {
-                    Some(Track(candidates)) 
-                })
+                    let end = track_notes.iter().map(|note| note.start + note.duration).max().unwrap_or(0);
+                    let bars = song_bars.iter().copied().filter(|&bar| bar == 0 || bar < end).collect();
+                    Some(Track { name, instrument, metadata: metadata.clone(), channels, notes: track_notes, ticks_per_beat, bars }) 
+                }
+            })
             .collect();
-        // Determine if every note in track is a duplicate
-        // TODO: This should not be necessary, find out why this happens
+
+        // Several tracks may be playing the same instrument, number them so every file name is different
+        let mut file_names: HashSet<String> = HashSet::new();
         for track in tracks.iter_mut()
         {
-            if track.notes().chunks(2).filter(|chunk| (chunk.len() == 2 && chunk[0] != chunk[1]) || chunk.len() == 1).next().is_none()
+            let name = track.name.clone();
+            for number in 2..
             {
-                for i in (0..track.notes().len()).rev()
+                if file_names.insert(track.file_name())
                 {
-                    if i % 2 == 0
-                    {
-                        track.notes_mut().remove(i);
-                    }
+                    break;
                 }
-                
+                track.name = format!("{} {}", name, number);
             }
         }
-        Song(tracks)
+        Song { metadata, report, tracks }
     }
 
     /// Output chart with each cell as an individual file
-    pub fn output_cells(&self, output_path: &str) -> Result<(), ImageError>
+    pub fn output_cells(&self, output_path: &str, config: &Config) -> Result<(), Error>
     {
-        for (i, track) in self.tracks().enumerate()
+        let size = config.size(OutputFormat::Separate);
+        for track in self.selected_tracks(config)
         {
-            let track_path = format!("{}/track{}", output_path, i);
+            let track_path = format!("{}/{}", output_path, track.file_name());
             fs::create_dir_all(&track_path)?;
-            for (cell, image) in track.cell_images().iter().enumerate()
+            match config.file_type
             {
-                let image = image.resize(image.width() * 2, image.height() * 2, FilterType::Nearest);
-                image.save(format!("{}/{}.png", track_path, cell))?;
+                FileType::Png => for (cell, image) in track.cell_images(config).iter().enumerate()
+                {
+                    scaled(image, size.scale(image.width())).save(format!("{}/{}.png", track_path, cell))?;
+                },
+                FileType::Svg => for (cell, svg) in track.cell_svgs(config).iter().enumerate()
+                {
+                    svg.save(&format!("{}/{}.svg", track_path, cell), size.scale(svg.width))?;
+                }
             }
         }
         Ok(())
     }
 
     /// Output chart with each row as an individual file
-    pub fn output_rows(&self, output_path: &str, notes_per_row: usize, spacing: usize) -> Result<(), ImageError>
+    pub fn output_rows(&self, output_path: &str, config: &Config) -> Result<(), Error>
     {
-        for (i, track) in self.tracks().enumerate()
+        let size = config.size(OutputFormat::Rows);
+        for track in self.selected_tracks(config)
         {
-            let track_path = format!("{}/track{}", output_path, i);
+            let track_path = format!("{}/{}", output_path, track.file_name());
             fs::create_dir_all(&track_path)?;
-            for (row, image) in track.row_images(notes_per_row, spacing).iter().enumerate() 
+            match config.file_type
             {
-                let image = image.resize(image.width() * 2, image.height() * 2, FilterType::Nearest);
-                image.save(format!("{}/row{}.png", track_path, row))?;
+                FileType::Png => for (row, image) in track.row_images(config, size).iter().enumerate() 
+                {
+                    image.save(format!("{}/row{}.png", track_path, row))?;
+                },
+                FileType::Svg => for (row, svg) in track.row_svgs(config).iter().enumerate()
+                {
+                    svg.save(&format!("{}/row{}.svg", track_path, row), size.scale(svg.width))?;
+                }
             }
         }
         Ok(())
     }
 
     /// Generate a chart and output to the given directory.
-    pub fn output_entire(&self, output_path: &str, notes_per_row: usize, spacing: usize) -> Result<(), ImageError>
+    pub fn output_entire(&self, output_path: &str, config: &Config) -> Result<(), Error>
+    {
+        fs::create_dir_all(output_path)?;
+        let size = config.size(OutputFormat::Tracks);
+        for track in self.selected_tracks(config)
+        {
+            match config.file_type
+            {
+                FileType::Png => track.track_image(config, size).save(format!("{}/{}.png", output_path, track.file_name()))?,
+                FileType::Svg =>
+                {
+                    let svg = track.track_svg(config);
+                    svg.save(&format!("{}/{}.svg", output_path, track.file_name()), size.scale(svg.width))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Generate paginated pdf documents and output to the given directory, either one per track or a single document.
+    /// The given title is used in page headers when the midi file doesn't name the song.
+    pub fn output_pdf(&self, output_path: &str, title: &str, config: &Config) -> Result<(), Error>
     {
         fs::create_dir_all(output_path)?;
-        for (i, track) in self.tracks().enumerate()
+        let title = self.metadata.title.as_deref().unwrap_or(title);
+        let tracks: Vec<(String, PdfTrack)> = self.selected_tracks(config)
+            .into_iter()
+            .map(|track| (track.file_name(), PdfTrack { name: track.name.clone(), rows: track.row_images(config, Size::Scale(1)) }))
+            .collect();
+        if config.pdf.single_document
+        {
+            let tracks: Vec<PdfTrack> = tracks.into_iter().map(|(_, track)| track).collect();
+            write_pdf(&format!("{}/all_tracks.pdf", output_path), title, &tracks, config.spacing, &config.pdf)?;
+        }
+        else
         {
-            let image = track.track_image(notes_per_row, spacing);
-            let image = image.resize(image.width() * 2, image.height() * 2, FilterType::Nearest);
-            image.save(format!("{}/track{}.png", output_path, i))?;
+            for (file_name, track) in &tracks
+            {
+                write_pdf(&format!("{}/{}.pdf", output_path, file_name), title, std::slice::from_ref(track), config.spacing, &config.pdf)?;
+            }
         }
         Ok(())
     }
 
+    /// Tracks to output. Tracks are selected by index, name and channel when the config lists any, then percussion 
+    /// and tracks with too few notes are skipped if enabled.
+    pub fn selected_tracks(&self, config: &Config) -> Vec<&Track>
+    {
+        self.tracks()
+            .enumerate()
+            .filter(|&(i, track)| skip_reason(i, track, config).is_none())
+            .map(|(_, track)| track)
+            .collect()
+    }
+
+    /// Tracks left out of the output, along with their index and the reason they are skipped
+    pub fn skipped_tracks(&self, config: &Config) -> Vec<(usize, &Track, String)>
+    {
+        self.tracks()
+            .enumerate()
+            .filter_map(|(i, track)| skip_reason(i, track, config).map(|reason| (i, track, reason)))
+            .collect()
+    }
+
     /// Access notes vector
-    fn tracks(&self) -> impl Iterator<Item = &Track>
+    pub fn tracks(&self) -> impl Iterator<Item = &Track>
+    {
+        self.tracks.iter()
+    }
+}
+
+/// Read the notes, names and measures of a midi file
+fn read_midi(path: &str, config: &Config) -> Result<RawSong, Error>
+{
+    let transposition = config.transposition_type as i16;
+
+    // Parse a midi file
+    let raw_data = fs::read(path)?;
+    let midi = Smf::parse(&raw_data)?;
+
+    // Determine how many ticks make up a beat. Timecode-based files count ticks per second instead, so
+    // the first tempo in the file is used to convert.
+    let ticks_per_beat = match midi.header.timing
+    {
+        Timing::Metrical(ticks) => ticks.as_int() as u32,
+        Timing::Timecode(fps, subframe) =>
+        {
+            let tempo = midi.tracks
+                .iter()
+                .flatten()
+                .find_map(|event| if let Meta(Tempo(tempo)) = event.kind { Some(tempo.as_int()) } else { None })
+                .unwrap_or(DEFAULT_TEMPO);
+            (fps.as_f32() * subframe as f32 * tempo as f32 / 1_000_000.0).round() as u32
+        }
+    }.max(1);
+
+    // Find the tick every event happens on, which fails if a track is too long to count in ticks
+    let timed_tracks: Vec<Vec<(u32, TrackEventKind)>> = midi.tracks.iter().map(|track| timed_events(track)).collect::<Result<_, _>>()?;
+
+    // Collect time signature changes from every track, these are usually only found in the first. Time signatures 
+    // whose measures are too long to count in ticks are left out with a warning.
+    let mut warnings = Vec::new();
+    let mut time_signatures: Vec<(u32, u32)> = Vec::new();
+    for &(tick, kind) in timed_tracks.iter().flatten()
+    {
+        if let Meta(TimeSignature(numerator, denominator, _, _)) = kind
+        {
+            match (numerator as u32).checked_mul(ticks_per_beat * 4).zip(2u32.checked_pow(denominator as u32))
+            {
+                Some((ticks, beat_type)) => time_signatures.push((tick, ticks / beat_type)),
+                None => warnings.push(format!("Time signature {}/2^{} at tick {} is too long, ignoring it", numerator, denominator, tick))
+            }
+        }
+    }
+    time_signatures.sort_by_key(|&(tick, _)| tick);
+
+    // Key signatures as well, which are moved into the key the notes are transposed to
+    let mut key_signatures: Vec<(u32, KeySignature)> = timed_tracks
+        .iter()
+        .flatten()
+        .filter_map(|&(tick, kind)| match kind
+        {
+            Meta(MetaMessage::KeySignature(sharps, minor)) => Some((tick, KeySignature { sharps, minor }.transposed(transposition))),
+            _ => None
+        })
+        .collect();
+    key_signatures.sort_by_key(|&(tick, _)| tick);
+    key_signatures.dedup_by_key(|&mut (tick, _)| tick);
+
+    // Collect program changes from every track as well, to determine the instrument each note is played with
+    let mut programs: Vec<(u32, u8, u8)> = timed_tracks
+        .iter()
+        .flatten()
+        .filter_map(|&(tick, kind)| match kind
+        {
+            Midi { channel, message: ProgramChange { program } } => Some((tick, channel.as_int(), program.as_int())),
+            _ => None
+        })
+        .collect();
+    programs.sort_by_key(|&(tick, _, _)| tick);
+
+    // Read names and text from meta events. In single track and parallel files the first track names the song.
+    let mut track_names: Vec<(Option<String>, Option<String>)> = midi.tracks.iter().map(|track| track_names(track)).collect();
+    let mut metadata = Metadata::default();
+    if midi.header.format != Format::Sequential
+    {
+        if let Some((name, _)) = track_names.first_mut()
+        {
+            metadata.title = name.take();
+        }
+    }
+    for (i, track) in midi.tracks.iter().enumerate()
+    {
+        for event in track
+        {
+            match event.kind
+            {
+                Meta(MetaMessage::Copyright(text)) if metadata.copyright.is_none() => metadata.copyright = meta_text(text),
+                Meta(MetaMessage::Text(text)) if i == 0 => metadata.text.extend(meta_text(text)),
+                _ => ()
+            }
+        }
+    }
+
+    // Collect notes from each track, then mark out measures up to the end of the last note
+    let tracks: Vec<Vec<RawNote>> = timed_tracks
+        .iter()
+        .map(|track| load_notes(track, transposition, &programs, config.repeated_notes))
+        .collect();
+    let end = tracks.iter().flatten().map(|note| note.start + note.duration).max().unwrap_or(0);
+    let bars = bar_lines(&time_signatures, ticks_per_beat, end);
+    Ok(RawSong { ticks_per_beat, bars, metadata, tracks, names: track_names, key_signatures, warnings })
+}
+
+/// Determine why a track should not be output, if it shouldn't be. The index is the track's position in its song.
+fn skip_reason(index: usize, track: &Track, config: &Config) -> Option<String>
+{
+    if !config.tracks.is_empty() && !config.tracks.contains(&index)
+    {
+        Some(String::from("not one of the selected track numbers"))
+    }
+    else if !config.track_names.is_empty() && !config.track_names.iter().any(|pattern| matches_pattern(&track.name, pattern))
     {
-        self.0.iter()
+        Some(format!("name doesn't match {}", config.track_names.join(", ")))
     }
+    else if !config.channels.is_empty() && !track.channels.iter().any(|channel| config.channels.contains(&(channel + 1)))
+    {
+        Some(String::from("not played on any of the selected channels"))
+    }
+    else if config.skip_percussion && track.is_percussion()
+    {
+        Some(format!("percussion (channel {})", PERCUSSION_CHANNEL + 1))
+    }
+    else if track.notes().len() < config.min_notes
+    {
+        Some(format!("only {} note(s), fewer than {}", track.notes().len(), config.min_notes))
+    }
+    else
+    {
+        None
+    }
+}
+
+/// Check whether a track name matches a pattern, ignoring case. `*` matches any number of characters and `?` matches 
+/// a single character. Patterns can match anywhere in the name.
+fn matches_pattern(name: &str, pattern: &str) -> bool
+{
+    let name: Vec<char> = name.to_lowercase().chars().collect();
+    let pattern: Vec<char> = format!("*{}*", pattern.to_lowercase()).chars().collect();
+
+    // Which prefixes of the name are matched by the pattern read so far
+    let mut matched = vec![false; name.len() + 1];
+    matched[0] = true;
+    for &p in &pattern
+    {
+        let mut next = vec![false; name.len() + 1];
+        for i in 0..=name.len()
+        {
+            next[i] = match p
+            {
+                '*' => matched[i] || (i > 0 && next[i - 1]),
+                '?' => i > 0 && matched[i - 1],
+                _ => i > 0 && matched[i - 1] && name[i - 1] == p
+            };
+        }
+        matched = next;
+    }
+    matched[name.len()]
 }
 
+/// Pair the events of a midi track with the absolute tick each one happens on, failing if the track is too long to 
+/// count in ticks
+fn timed_events<'a>(track: &[TrackEvent<'a>]) -> Result<Vec<(u32, TrackEventKind<'a>)>, Error>
+{
+    let mut tick: u32 = 0;
+    track
+        .iter()
+        .map(|event|
+        {
+            tick = tick.checked_add(event.delta.as_int()).ok_or_else(|| Error::Score(String::from("midi track is too long")))?;
+            Ok((tick, event.kind))
+        })
+        .collect()
+}
+
+/// Read the name of a midi track and the name of the instrument it is played on, using the first of each found
+fn track_names(track: &[TrackEvent]) -> (Option<String>, Option<String>)
+{
+    let (mut name, mut instrument) = (None, None);
+    for event in track
+    {
+        match event.kind
+        {
+            Meta(MetaMessage::TrackName(text)) if name.is_none() => name = meta_text(text),
+            Meta(MetaMessage::InstrumentName(text)) if instrument.is_none() => instrument = meta_text(text),
+            _ => ()
+        }
+    }
+    (name, instrument)
+}
+
+/// Decode the text of a meta event, which is usually ASCII but may be UTF-8 or Latin-1. Surrounding whitespace and
+/// padding is removed, and empty text is treated as missing.
+fn meta_text(bytes: &[u8]) -> Option<String>
+{
+    let text = match std::str::from_utf8(bytes)
+    {
+        Ok(text) => text.to_string(),
+        Err(_) => bytes.iter().map(|&byte| byte as char).collect()
+    };
+    let text = text.trim_matches(|c: char| c.is_whitespace() || c.is_control());
+    if text.is_empty() { None } else { Some(text.to_string()) }
+}
+
+/// Collect the notes of a single midi track, given each event along with the tick it happens on. Notes end on a 
+/// NoteOff event or on a NoteOn event with a velocity of 0. Notes which are never ended are held until the end of 
+/// the track. Programs are given as the tick they take effect on, the channel and the program number, in order. A 
+/// note started again on the same tick it started is a duplicate event rather than a new note, otherwise the 
+/// repeated notes policy decides.
+fn load_notes(track: &[(u32, TrackEventKind)], transposition: i16, programs: &[(u32, u8, u8)], repeated: RepeatedNotes) -> Vec<RawNote>
+{
+    let mut notes: Vec<RawNote> = Vec::new();
+
+    // Notes currently being held, keyed by channel and key, along with how many ends they are still waiting for. A 
+    // retriggered note takes over the ends its held note was waiting for, so the last end stops it.
+    let mut held: HashMap<(u8, u8), (usize, u32)> = HashMap::new();
+    let mut tick: u32 = 0;
+    for &(event_tick, kind) in track
+    {
+        tick = event_tick;
+        match kind
+        {
+            Midi { channel, message: NoteOn { key, vel } } if vel > 0 =>
+            {
+                let channel = channel.as_int();
+                let mut pending = 1;
+                if let Some((i, held_pending)) = held.get_mut(&(channel, key.as_int()))
+                {
+                    *held_pending += 1;
+                    if notes[*i].start == tick || repeated == RepeatedNotes::Legato
+                    {
+                        continue;
+                    }
+                    notes[*i].duration = tick - notes[*i].start;
+                    pending = *held_pending;
+                }
+                let program = programs
+                    .iter()
+                    .rev()
+                    .find(|&&(start, program_channel, _)| start <= tick && program_channel == channel)
+                    .map(|&(_, _, program)| program);
+                held.insert((channel, key.as_int()), (notes.len(), pending));
+                notes.push(RawNote { key: key.as_int() as i16 + transposition, velocity: vel.as_int(), channel, program, start: tick, duration: 0, spelling: None, fingering: None });
+            },
+            Midi { channel, message: NoteOff { key, vel: _ } } | Midi { channel, message: NoteOn { key, vel: _ } } =>
+            {
+                let id = (channel.as_int(), key.as_int());
+                if let Some((i, pending)) = held.get_mut(&id)
+                {
+                    *pending -= 1;
+                    if *pending == 0
+                    {
+                        notes[*i].duration = tick - notes[*i].start;
+                        held.remove(&id);
+                    }
+                }
+            },
+            _ => ()
+        }
+    }
+    for (i, _) in held.into_values()
+    {
+        notes[i].duration = tick - notes[i].start;
+    }
+    notes
+}
+
+/// Group the notes of each midi track into named tracks, given the track and instrument names of each midi track. 
+/// Midi tracks are named by their track name, their instrument name, the instrument they start with or their number,
+/// whichever is found first. Tracks split by channel are named by the instrument they start with or their channel.
+fn split_notes(midi_tracks: Vec<Vec<RawNote>>, names: Vec<(Option<String>, Option<String>)>, split_by: SplitBy) -> Vec<RawTrack>
+{
+    let program_name = |note: &RawNote| instrument_name(note.channel, note.program).map(str::to_string);
+    match split_by
+    {
+        SplitBy::Track => midi_tracks
+            .into_iter()
+            .zip(names)
+            .enumerate()
+            .filter(|(_, (raw_notes, _))| !raw_notes.is_empty())
+            .map(|(i, (raw_notes, (name, instrument)))|
+            {
+                let instrument = instrument.or_else(|| program_name(&raw_notes[0]));
+                let name = name.or_else(|| instrument.clone()).unwrap_or_else(|| format!("Track {}", i + 1));
+                RawTrack { name, instrument, notes: raw_notes }
+            })
+            .collect(),
+        SplitBy::Channel | SplitBy::Program =>
+        {
+            let mut groups: BTreeMap<(u8, Option<u8>), Vec<RawNote>> = BTreeMap::new();
+            for note in midi_tracks.into_iter().flatten()
+            {
+                let program = if split_by == SplitBy::Program { note.program } else { None };
+                groups.entry((note.channel, program)).or_default().push(note);
+            }
+            groups
+                .into_values()
+                .map(|mut raw_notes|
+                {
+                    // Notes from different midi tracks are interleaved by start time
+                    raw_notes.sort_by_key(|note| note.start);
+                    let instrument = program_name(&raw_notes[0]);
+                    let name = instrument.clone().unwrap_or_else(|| format!("Channel {}", raw_notes[0].channel + 1));
+                    RawTrack { name, instrument, notes: raw_notes }
+                })
+                .collect()
+        }
+    }
+}
+
+/// Deal with notes which are played at the same time, either by keeping a single note from each chord or by 
+/// splitting chords into separate voices. Returns the resulting voices and the number of notes that were either 
+/// removed or moved out of the first voice.
+fn reduce_polyphony(notes: Vec<RawNote>, polyphony: Polyphony, ticks_per_beat: u32) -> (Vec<Vec<RawNote>>, usize)
+{
+    if polyphony == Polyphony::Keep
+    {
+        return (vec![notes], 0);
+    }
+
+    // Group notes into chords by start time. Notes are already in the order they started.
+    let tolerance = ticks_per_beat / CHORD_FRACTION;
+    let mut chords: Vec<Vec<RawNote>> = Vec::new();
+    for note in notes
+    {
+        match chords.last_mut()
+        {
+            Some(chord) if note.start - chord[0].start <= tolerance => chord.push(note),
+            _ => chords.push(vec![note])
+        }
+    }
+
+    let mut voices: Vec<Vec<RawNote>> = vec![Vec::new()];
+    let mut reduced = 0;
+    for mut chord in chords
+    {
+        reduced += chord.len() - 1;
+        match polyphony
+        {
+            Polyphony::Split =>
+            {
+                // The highest note goes in the first voice, the next highest in the second, and so on
+                chord.sort_by_key(|note| Reverse(note.key));
+                for (i, note) in chord.into_iter().enumerate()
+                {
+                    if i == voices.len()
+                    {
+                        voices.push(Vec::new());
+                    }
+                    voices[i].push(note);
+                }
+            },
+            _ =>
+            {
+                let keep = match polyphony
+                {
+                    Polyphony::Lowest => chord.iter().enumerate().min_by_key(|(_, note)| note.key),
+                    Polyphony::Loudest => chord.iter().enumerate().max_by_key(|(_, note)| (note.velocity, note.key)),
+                    _ => chord.iter().enumerate().max_by_key(|(_, note)| note.key)
+                }.map_or(0, |(i, _)| i);
+                voices[0].push(chord.swap_remove(keep));
+            }
+        }
+    }
+    (voices, reduced)
+}
+
+/// Match raw notes against the notes in the config, shifting each by the given number of semitones. 
+/// Notes which are still out of range are either folded by octave into range or left out, with a warning for each.
+fn resolve_notes(raw_notes: Vec<RawNote>, config_notes: &Notes, shift: i16, fold: bool, out_of_range: &mut HashSet<i16>, warnings: &mut Vec<String>) 
+    -> Vec<TrackNote>
+{
+    let mut notes: Vec<TrackNote> = Vec::with_capacity(raw_notes.len());
+    for raw_note in raw_notes
+    {
+        let key = raw_note.key + shift;
+        let note = get_note(config_notes, key).or_else(|| if fold 
+        { 
+            (1..=10).flat_map(|octave| [key - octave * 12, key + octave * 12]).find_map(|key| get_note(config_notes, key)) 
+        } 
+        else 
+        { 
+            None 
+        });
+        match note
+        {
+            None =>
+            {
+                if out_of_range.insert(key)
+                {
+                    warnings.push(format!("Note out of range: {}", key));
+                }
+            },
+            Some(note) =>
+            {
+                if fold && note.byte as i16 != key && out_of_range.insert(key)
+                {
+                    warnings.push(format!("Note out of range, folded by octave: {} -> {}", key, note.byte));
+                }
+                let spelling = raw_note.spelling.and_then(|spelling| spelling.transposed(raw_note.key, note.byte as i16));
+                let fingering = raw_note.fingering.filter(|&fingering|
+                {
+                    let exists = fingering < note.fingerings.len();
+                    if !exists
+                    {
+                        warnings.push(format!("Note {} has no fingering {}, selecting one instead", note.byte, fingering));
+                    }
+                    exists
+                });
+                notes.push(TrackNote { note: Arc::clone(note), start: raw_note.start, duration: raw_note.duration, rest: 0, spelling, fingering });
+            }
+        }
+    }
+
+    // Rests are the gaps between the end of the latest-ending note so far and the start of the next
+    let mut end = 0;
+    for note in notes.iter_mut()
+    {
+        note.rest = note.start.saturating_sub(end);
+        end = end.max(note.start + note.duration);
+    }
+    notes
+}
+
+/// Spell every note that its file doesn't spell in the key signature it's played in. Key signatures come from the 
+/// file, the config or are detected from the notes, as set in the config. Notes are spelled before they are moved 
+/// into range, so their spelling moves with them. Returns the key if it was detected.
+fn spell_notes(tracks: &mut [Vec<RawNote>], key_signatures: Vec<(u32, KeySignature)>, config: &Config) -> Option<KeySignature>
+{
+    if tracks.iter().flatten().all(|note| note.spelling.is_some())
+    {
+        return None;
+    }
+    let mut detected = None;
+    let key_signatures = match config.key_signature
+    {
+        KeySignatures::Fixed(key) => vec![(0, key.transposed(config.transposition_type as i16))],
+        KeySignatures::Auto if !key_signatures.is_empty() => key_signatures,
+        _ =>
+        {
+            let key = KeySignature::detect(tracks.iter().flatten().map(|note| (note.key, note.duration)));
+            detected = Some(key);
+            vec![(0, key)]
+        }
+    };
+    for note in tracks.iter_mut().flatten().filter(|note| note.spelling.is_none())
+    {
+        // The key signature in effect when the note starts, notes before the first one use the first
+        let key = key_signatures
+            .iter()
+            .take_while(|&&(tick, _)| tick <= note.start)
+            .last()
+            .unwrap_or(&key_signatures[0]).1;
+        note.spelling = Some(key.spell(note.key));
+    }
+    detected
+}
+
+/// Look up a note from a key which may be outside of the midi range
+fn get_note(notes: &Notes, key: i16) -> Option<&Arc<Note>>
+{
+    u8::try_from(key).ok().and_then(|byte| notes.get(byte))
+}
+
+/// Determine the shift in semitones that fits the most notes into range. The best octave shift is found 
+/// first, and only if some notes still don't fit is it adjusted by semitone. Smaller shifts are preferred.
+fn best_shift(keys: &[i16], notes: &Notes) -> i16
+{
+    let fits = |shift: i16| keys.iter().filter(|&&key| get_note(notes, key + shift).is_some()).count();
+    let best = |shifts: &mut dyn Iterator<Item = i16>, center: i16| shifts
+        .max_by_key(|&shift| (fits(shift), Reverse((shift - center).abs()), shift))
+        .unwrap_or(center);
+
+    let octave = best(&mut (-10..=10).map(|octave| octave * 12), 0);
+    if fits(octave) == keys.len()
+    {
+        return octave;
+    }
+    best(&mut (-11..=11).map(|semitone| octave + semitone), octave)
+}
+
+/// Determine the tick each measure starts on, up to the given end tick. Time signatures are given as 
+/// the tick they take effect on and the length of a measure in ticks. Songs without any time signature are in 4/4.
+fn bar_lines(time_signatures: &[(u32, u32)], ticks_per_beat: u32, end: u32) -> Vec<u32>
+{
+    let mut bars = vec![0];
+    let mut bar = 0;
+    let mut measure_length = ticks_per_beat * 4;
+    let mut changes = time_signatures.iter().peekable();
+    loop
+    {
+        while let Some(&(_, length)) = changes.next_if(|&&(tick, _)| tick <= bar)
+        {
+            measure_length = length.max(1);
+        }
+
+        // A time signature change part way through a measure starts a new measure
+        let next = match changes.peek()
+        {
+            Some(&&(tick, _)) if tick < bar.saturating_add(measure_length) => tick,
+            _ => bar.saturating_add(measure_length)
+        };
+        if next >= end
+        {
+            return bars;
+        }
+        bars.push(next);
+        bar = next;
+    }
+}
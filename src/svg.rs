@@ -0,0 +1,253 @@
+use crate::{Config, theme::{Color, Theme}, keys::{Key, visible_keys, font::{GLYPH_HEIGHT, LINE_HEIGHT}, image_data::*}, note::{Note, Spelling, Accidental}, track::{Track, Cell, cell_height, header_size}};
+use enumset::EnumSet;
+use std::{fs, io};
+
+/// A vector image. Coordinates are in the same units as the pixels of raster charts.
+pub struct Svg
+{
+    pub width: u32,
+    pub height: u32,
+    body: String
+}
+
+impl Svg
+{
+    /// Lay out images left to right with spacing around them on the theme's background, matching raster rows
+    pub fn row(cells: &[Svg], spacing: usize, theme: &Theme) -> Svg
+    {
+        let spacing = spacing as u32;
+        let mut body = String::new();
+        let mut width = spacing;
+        for cell in cells
+        {
+            body.push_str(&cell.translated(width, 0));
+            width += cell.width + spacing;
+        }
+        let height = cells.iter().map(|cell| cell.height).max().unwrap_or(0);
+        Svg { width, height, body }.with_background(theme.background)
+    }
+
+    /// Lay out images top to bottom with spacing around them on the theme's background, matching raster tracks
+    pub fn column(rows: &[Svg], spacing: usize, theme: &Theme) -> Svg
+    {
+        let spacing = spacing as u32;
+        let mut body = String::new();
+        let mut height = spacing;
+        for row in rows
+        {
+            body.push_str(&row.translated(0, height));
+            height += row.height + spacing;
+        }
+        let width = rows.iter().map(|row| row.width).max().unwrap_or(0);
+        Svg { width, height, body }.with_background(theme.background)
+    }
+
+    /// Save as a standalone svg document. Like raster charts, the document size is the chart size times the scale.
+    pub fn save(&self, path: &str, scale: u32) -> io::Result<()>
+    {
+        fs::write(path, format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\">\n{}</svg>\n",
+            self.width * scale, self.height * scale, self.width, self.height, self.body))
+    }
+
+    /// The body of this image moved by an offset
+    fn translated(&self, x: u32, y: u32) -> String
+    {
+        format!("<g transform=\"translate({} {})\">\n{}</g>\n", x, y, self.body)
+    }
+
+    /// Move this image down to make room for lines of text above it, laid out like the header of raster charts
+    fn with_header(self, header: &[String], spacing: usize, theme: &Theme) -> Svg
+    {
+        if header.is_empty()
+        {
+            return self;
+        }
+        let (header_width, header_height) = header_size(header, spacing);
+        let mut body = String::new();
+        for (i, line) in header.iter().enumerate()
+        {
+            let baseline = spacing as u32 + i as u32 * LINE_HEIGHT + GLYPH_HEIGHT;
+            body.push_str(&format!(
+                "<text x=\"{}\" y=\"{}\" font-family=\"monospace\" font-size=\"9\" fill=\"{}\">{}</text>\n",
+                spacing, baseline, theme.font.hex(), escape(line)));
+        }
+        body.push_str(&self.translated(0, header_height));
+        Svg { width: self.width.max(header_width), height: self.height + header_height, body }.with_background(theme.background)
+    }
+
+    /// Fill everything behind this image with a color
+    fn with_background(mut self, color: Color) -> Svg
+    {
+        self.body.insert_str(0, &rect(0.0, 0.0, self.width as f32, self.height as f32, color));
+        self
+    }
+}
+
+impl Track
+{
+    /// Generates a vector image for the entire track
+    pub fn track_svg(&self, config: &Config) -> Svg
+    {
+        let theme = config.theme.resolve();
+        Svg::column(&self.row_svgs(config), config.spacing, &theme).with_header(&self.header(config), config.spacing, &theme)
+    }
+
+    /// Generates vector images in rows
+    pub fn row_svgs(&self, config: &Config) -> Vec<Svg>
+    {
+        let theme = config.theme.resolve();
+        self.rows(config)
+            .iter()
+            .map(|cells| Svg::row(&cells.iter().map(|cell| cell.svg(config, &theme)).collect::<Vec<_>>(), config.spacing, &theme))
+            .collect()
+    }
+
+    /// Generates vector images for each cell
+    pub fn cell_svgs(&self, config: &Config) -> Vec<Svg>
+    {
+        let theme = config.theme.resolve();
+        self.cells(config).iter().map(|cell| cell.svg(config, &theme)).collect()
+    }
+}
+
+impl Cell<'_>
+{
+    /// Draws this cell as a vector image, with the theme's padding around it
+    pub fn svg(&self, config: &Config, theme: &Theme) -> Svg
+    {
+        let height = cell_height(config, theme);
+        let body = match *self
+        {
+            Cell::Note(note, fingering, spelling, beats) =>
+            {
+                let mut body = fingering_svg(fingering.keys, theme);
+                body.push_str(&note_name_svg(note.byte, spelling.unwrap_or_else(|| Note::spelling(note.byte)), config, theme));
+                if config.show_durations
+                {
+                    body.push_str(&duration_svg(beats, theme));
+                }
+                body
+            },
+            Cell::Rest(beats) =>
+            {
+                let (x, y) = (REST_LOCATION.0 as f32, REST_LOCATION.1 as f32);
+                let (rest_width, rest_height) = (REST_SIZE.0 as f32, REST_SIZE.1 as f32);
+                let mut body = rect(x, y + rest_height - 1.0, rest_width, 1.0, theme.filled);
+                body.push_str(&rect(x + rest_width / 4.0, y, rest_width / 2.0, rest_height - 1.0, theme.filled));
+                if config.show_durations
+                {
+                    body.push_str(&duration_svg(beats, theme));
+                }
+                body
+            },
+            Cell::BarLine =>
+            {
+                return Svg { width: BAR_LINE_WIDTH as u32, height, body: rect(0.0, 0.0, BAR_LINE_WIDTH as f32, height as f32, theme.font) };
+            }
+        };
+        let chart = Svg { width: CHART_SIZE.0 as u32, height: height - theme.padding * 2, body };
+        let body = chart.translated(theme.padding, theme.padding);
+        Svg { width: chart.width + theme.padding * 2, height, body }.with_background(theme.chart_background)
+    }
+}
+
+/// Draws the keys of a fingering, using the same positions and sizes as the raster charts
+fn fingering_svg(keys: EnumSet<Key>, theme: &Theme) -> String
+{
+    let mut body = String::new();
+    let (x, y) = (SEP_LOCATION.0 as f32, SEP_LOCATION.1 as f32);
+    body.push_str(&rect(x, y, SEPARATOR.width() as f32, SEPARATOR.height() as f32, theme.separator));
+    for key in visible_keys(keys)
+    {
+        body.push_str(&key.svg(keys.contains(key), theme));
+    }
+    body
+}
+
+/// Draws the name of a note in the same place as on raster charts, along with its octave number if enabled
+fn note_name_svg(byte: u8, spelling: Spelling, config: &Config, theme: &Theme) -> String
+{
+    let color = theme.note_names.map_or_else(|| Color::from(config.octave_colors.color(byte)).hex(), Color::hex);
+    let baseline = NAME_LOCATION.1 as u32 + C.height();
+    let shift = if config.show_octave { OCTAVE_SHIFT } else { 0 };
+    let text = |x: u8, size: u32, text: &str| format!(
+        "<text x=\"{}\" y=\"{}\" font-family=\"sans-serif\" font-weight=\"bold\" font-size=\"{}\" fill=\"{}\">{}</text>\n",
+        x - shift, baseline, size, color, text);
+    let mut body = text(NAME_LOCATION.0, 14, &spelling.letter.to_string());
+    let accidental = match spelling.accidental
+    {
+        Accidental::Natural => None,
+        Accidental::Flat => Some('\u{266D}'),
+        Accidental::Sharp => Some('\u{266F}')
+    };
+    if let Some(accidental) = accidental
+    {
+        body.push_str(&text(ACCIDENTAL_LOCATION.0, 14, &accidental.to_string()));
+    }
+    if config.show_octave
+    {
+        let left = if accidental.is_some() { ACCIDENTAL_LOCATION.0 } else { NAME_LOCATION.0 };
+        body.push_str(&text(left + NAME_WIDTH + 1, 9, &Note::octave(byte).to_string()));
+    }
+    body
+}
+
+/// Draws a duration bar beneath a chart, with a notch between each beat
+fn duration_svg(beats: f32, theme: &Theme) -> String
+{
+    let top = CHART_SIZE.1 as f32 + 1.0;
+    let height = DURATION_HEIGHT as f32 - 1.0;
+    let width = (beats * BEAT_WIDTH as f32).round().clamp(1.0, CHART_SIZE.0 as f32 - 2.0);
+    let beat = BEAT_WIDTH as f32;
+    let mut body = String::new();
+    let mut start = 0.0;
+    while start < width
+    {
+        let end = (start + beat - 1.0).min(width);
+        body.push_str(&rect(start + 1.0, top, end - start, height, theme.filled));
+        start += beat;
+    }
+    body
+}
+
+impl Key
+{
+    /// Draws this key as a vector shape the size of its raster image. Pressed keys are filled.
+    fn svg(&self, filled: bool, theme: &Theme) -> String
+    {
+        let (x, y, image, _) = self.get_image_data();
+        let (width, height) = (image.width() as f32, image.height() as f32);
+        let (fill, stroke) = if filled { (theme.filled.hex(), theme.filled.hex()) } else { (theme.chart_background.hex(), theme.unfilled.hex()) };
+
+        // Shapes are inset by half a stroke so they fit within the raster image bounds
+        let (x, y, width, height) = (x as f32 + 0.5, y as f32 + 0.5, width - 1.0, height - 1.0);
+        match self
+        {
+            Key::Left1 | Key::Left2 | Key::Left3 | Key::Right1 | Key::Right2 | Key::Right3 | Key::Bis |
+            Key::Octave | Key::FrontF | Key::PalmD | Key::PalmEflat | Key::PalmF =>
+            {
+                format!("<ellipse cx=\"{}\" cy=\"{}\" rx=\"{}\" ry=\"{}\" fill=\"{}\" stroke=\"{}\"/>\n",
+                    x + width / 2.0, y + height / 2.0, width / 2.0, height / 2.0, fill, stroke)
+            },
+            _ =>
+            {
+                format!("<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" rx=\"2\" fill=\"{}\" stroke=\"{}\"/>\n",
+                    x, y, width, height, fill, stroke)
+            }
+        }
+    }
+}
+
+/// A filled rectangle
+fn rect(x: f32, y: f32, width: f32, height: f32, color: Color) -> String
+{
+    format!("<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\"/>\n", x, y, width, height, color.hex())
+}
+
+/// Escape text for use in svg
+fn escape(text: &str) -> String
+{
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
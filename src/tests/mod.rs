@@ -1,11 +1,153 @@
+use crate::{Config, Notes, Song};
+use midly::{Smf, Header, Format, Timing, TrackEvent, TrackEventKind, MidiMessage};
+
 const OUTPUT_DIR: &str = "test_out";
 
+// The default config and its notes, shared between tests since generating every fingering chart is slow
+lazy_static!
+{
+    static ref CONFIG: Config = crate::layer::load(None).expect("Failed to load config");
+    static ref NOTES: Notes = Notes::new(&CONFIG).expect("Failed to load notes");
+}
+
+/// The default config with songs left untransposed and chords kept, so tests see notes exactly as written
+fn test_config() -> Config
+{
+    let mut config = crate::layer::load(None).expect("Failed to load config");
+    config.transposition_type = crate::TranspositionType::CMelody;
+    config.auto_transpose = false;
+    config.polyphony = crate::Polyphony::Keep;
+    config
+}
+
+/// Load a song using the notes from the default config
+fn load_song(path: &str, config: &Config) -> Song
+{
+    Song::load(path, &NOTES, config).unwrap_or_else(|e| panic!("Failed to load {}: {}", path, e))
+}
+
+/// Write a single-track midi file to the test output directory, returning its path
+fn write_midi(name: &str, events: &[(u32, TrackEventKind<'static>)]) -> String
+{
+    write_midi_tracks(name, Format::SingleTrack, &[events])
+}
+
+/// Write a midi file with any number of tracks to the test output directory, returning its path
+fn write_midi_tracks(name: &str, format: Format, tracks: &[&[(u32, TrackEventKind<'static>)]]) -> String
+{
+    std::fs::create_dir_all(OUTPUT_DIR).expect("Failed to create test output dir");
+    let mut smf = Smf::new(Header::new(format, Timing::Metrical(480.into())));
+    for events in tracks
+    {
+        let mut track: Vec<TrackEvent> = events
+            .iter()
+            .map(|&(delta, kind)| TrackEvent { delta: delta.into(), kind })
+            .collect();
+        track.push(TrackEvent { delta: 0.into(), kind: TrackEventKind::Meta(midly::MetaMessage::EndOfTrack) });
+        smf.tracks.push(track);
+    }
+    let path = format!("{}/{}.mid", OUTPUT_DIR, name);
+    smf.save(&path).expect("Failed to save midi file");
+    path
+}
+
+/// Shorthand for a midi note event on channel 0
+fn note_event(on: bool, key: u8, vel: u8) -> TrackEventKind<'static>
+{
+    channel_event(0, on, key, vel)
+}
+
+/// Shorthand for a midi note event on any channel
+fn channel_event(channel: u8, on: bool, key: u8, vel: u8) -> TrackEventKind<'static>
+{
+    let message = if on
+    {
+        MidiMessage::NoteOn { key: key.into(), vel: vel.into() }
+    }
+    else
+    {
+        MidiMessage::NoteOff { key: key.into(), vel: vel.into() }
+    };
+    TrackEventKind::Midi { channel: channel.into(), message }
+}
+
+/// Note durations and rests are read from NoteOn/NoteOff pairs
+#[test]
+fn note_timing()
+{
+    let path = write_midi("note_timing", &[
+        (0, note_event(true, 60, 64)),
+        (480, note_event(true, 60, 0)),
+        (480, note_event(true, 62, 64)),
+        (240, note_event(false, 62, 0)),
+        (0, note_event(true, 64, 64)),
+        (1920, note_event(false, 64, 0))
+    ]);
+    let song = load_song(&path, &test_config());
+    let track = song.tracks().next().expect("Track is missing");
+    assert_eq!(track.ticks_per_beat, 480);
+    let timing: Vec<(u8, u32, u32, u32)> = track.notes()
+        .iter()
+        .map(|note| (note.note.byte, note.start, note.duration, note.rest))
+        .collect();
+    assert_eq!(timing, vec![(60, 0, 480, 0), (62, 960, 240, 480), (64, 1200, 1920, 0)]);
+}
+
+/// Notes ended by a NoteOn with a velocity of 0 are not doubled up, with or without running status. Repeated notes
+/// are kept, even when every note is repeated.
+#[test]
+fn note_off_forms()
+{
+    for path in ["src/tests/running_status.mid", "src/tests/velocity_zero.mid"]
+    {
+        let song = load_song(path, &test_config());
+        let track = song.tracks().next().expect("Track is missing");
+        let timing: Vec<(u8, u32, u32)> = track.notes().iter().map(|note| (note.note.byte, note.start, note.duration)).collect();
+        assert_eq!(timing, vec![(60, 0, 480), (60, 480, 480), (62, 960, 480), (62, 1440, 480)], "{}", path);
+    }
+}
+
+/// A note started again while it's held either retriggers or carries on, and duplicate events on the same tick are
+/// merged into a single note
+#[test]
+fn repeated_notes()
+{
+    use crate::RepeatedNotes;
+
+    let path = write_midi("repeated_notes", &[
+        (0, note_event(true, 60, 64)),
+        (240, note_event(true, 60, 64)),
+        (240, note_event(false, 60, 0)),
+        (240, note_event(false, 60, 0)),
+        (0, note_event(true, 62, 64)),
+        (0, note_event(true, 62, 64)),
+        (480, note_event(false, 62, 0)),
+        (0, note_event(false, 62, 0))
+    ]);
+    let timing = |path: &str, repeated_notes: RepeatedNotes|
+    {
+        let mut config = test_config();
+        config.repeated_notes = repeated_notes;
+        config.min_notes = 0;
+        let song = load_song(path, &config);
+        let track = song.tracks().next().expect("Track is missing");
+        track.notes().iter().map(|note| (note.note.byte, note.start, note.duration)).collect::<Vec<_>>()
+    };
+    assert_eq!(timing(&path, RepeatedNotes::Retrigger), vec![(60, 0, 240), (60, 240, 480), (62, 720, 480)]);
+    assert_eq!(timing(&path, RepeatedNotes::Legato), vec![(60, 0, 720), (62, 720, 480)]);
+
+    // The first note ends after the retrigger, and the retriggered note carries on until the second end
+    let path = "src/tests/overlapping_notes.mid";
+    assert_eq!(timing(path, RepeatedNotes::Retrigger), vec![(60, 0, 240), (60, 240, 480)]);
+    assert_eq!(timing(path, RepeatedNotes::Legato), vec![(60, 0, 720)]);
+}
+
 /// Prints out each note image
 #[test]
 fn output_notes()
 {
     std::fs::create_dir_all(OUTPUT_DIR).expect("Failed to create test output dir");
-    for note in (46..78).filter_map(crate::note::Note::get)
+    for note in (46..78).filter_map(|byte| NOTES.get(byte))
     {
         for fingering in &note.fingerings
         {
@@ -16,6 +158,110 @@ fn output_notes()
     };
 }
 
+/// Writes a reference chart of every note, with a row per note that widens with its number of fingerings
+#[test]
+fn reference_chart()
+{
+    use crate::reference::{fingering_label, output_reference, pitch_label, reference_rows};
+
+    assert_eq!(pitch_label(58), "Bb3");
+    assert_eq!(pitch_label(60), "C4");
+    assert_eq!(fingering_label(2, Some("bis")), "2 bis");
+    assert_eq!(fingering_label(0, None), "0");
+
+    let rows = reference_rows(&NOTES, &CONFIG);
+    assert_eq!(rows.len(), CONFIG.notes.len());
+    let bytes: Vec<u8> = NOTES.iter().map(|note| note.byte).collect();
+    assert!(bytes.windows(2).all(|pair| pair[0] < pair[1]));
+    let width = |byte: u8| rows[bytes.iter().position(|&b| b == byte).expect("Missing note")].width();
+    assert!(width(58) > width(60), "Bb3 has more fingerings than C4");
+
+    output_reference(&NOTES, OUTPUT_DIR, &CONFIG).expect("Failed to write reference");
+    let pdf = std::fs::read(format!("{}/reference.pdf", OUTPUT_DIR)).expect("Failed to read reference");
+    assert!(pdf.starts_with(b"%PDF"));
+}
+
+/// Note names are colored by octave from a palette, and can be followed by their octave number
+#[test]
+fn octave_labels()
+{
+    use crate::{keys::image_data::*, note::FingeringConfig, Fingering, Palette};
+    use image::{GenericImageView, Rgba};
+
+    assert_eq!(Palette::Default.color(46), OCTAVE_2_COLOR);
+    assert_eq!(Palette::Default.color(72), OCTAVE_5_COLOR);
+    assert_ne!(Palette::Default.color(84), OCTAVE_5_COLOR);
+    for palette in [Palette::Default, Palette::ColorBlind, Palette::Grayscale]
+    {
+        let colors: Vec<_> = (2..=7).map(|octave| palette.color(octave * 12 + 12)).collect();
+        assert!(colors.iter().enumerate().all(|(i, color)| !colors[..i].contains(color)), "{:?} repeats a color", palette);
+        assert_eq!(palette.color(0), colors[0]);
+        assert_eq!(palette.color(127), colors[5]);
+    }
+
+    std::fs::create_dir_all(OUTPUT_DIR).expect("Failed to create test output dir");
+    let path = format!("{}/octave_labels.ron", OUTPUT_DIR);
+    std::fs::write(&path, "Config(show_octave: true, octave_colors: Custom({ 3: (1, 2, 3), 5: (4, 5, 6) }))").expect("Failed to write config");
+    let mut config = crate::layer::load(Some(&path)).expect("Failed to load config");
+    assert!(config.show_octave);
+    assert_eq!(config.octave_colors.color(40), Rgba([1, 2, 3, 255]));
+    assert_eq!(config.octave_colors.color(70), Rgba([1, 2, 3, 255]));
+    assert_eq!(config.octave_colors.color(90), Rgba([4, 5, 6, 255]));
+
+    // The octave number adds to the pixels drawn in the name's color
+    let fingering = FingeringConfig { keys: enumset::enum_set!(crate::keys::Key::Left1), preferred: false, name: None };
+    let colored = |config: &crate::Config|
+    {
+        let image = Fingering::new(&fingering, 70, config, &config.theme.resolve()).image;
+        image.pixels().filter(|&(_, _, color)| color == Rgba([1, 2, 3, 255])).count()
+    };
+    let labeled = colored(&config);
+    config.show_octave = false;
+    assert!(labeled > colored(&config));
+}
+
+/// Built-in and custom themes resolve to colors, which charts are drawn in along with the theme's padding
+#[test]
+fn themes()
+{
+    use crate::{keys::{with_padding, image_data::CHART_SIZE}, note::FingeringConfig, theme::{Color, Theme, Themes}, track::cell_height, Fingering};
+    use image::{GenericImageView, Rgba};
+
+    assert_eq!(Themes::Default.resolve(), Theme::default());
+    for theme in [Themes::Dark, Themes::HighContrast, Themes::Print]
+    {
+        let resolved = theme.resolve();
+        assert_ne!(resolved.filled, resolved.chart_background, "{:?} hides pressed keys", theme);
+        assert_ne!(resolved.font, resolved.background, "{:?} hides headers", theme);
+    }
+
+    std::fs::create_dir_all(OUTPUT_DIR).expect("Failed to create test output dir");
+    let path = format!("{}/themes.ron", OUTPUT_DIR);
+    std::fs::write(&path, "Config(theme: Custom((chart_background: (1, 2, 3), filled: (4, 5, 6), note_names: (7, 8, 9), padding: 3)))")
+        .expect("Failed to write config");
+    let mut config = crate::layer::load(Some(&path)).expect("Failed to load config");
+    let theme = config.theme.resolve();
+    assert_eq!(theme.background, Theme::default().background);
+    assert_eq!(theme.note_names, Some(Color(7, 8, 9)));
+    assert_eq!(theme.padding, 3);
+
+    // Charts are drawn in the theme's colors, and padding grows every cell
+    let fingering = FingeringConfig { keys: enumset::enum_set!(crate::keys::Key::Left1), preferred: false, name: None };
+    let image = Fingering::new(&fingering, 70, &config, &theme).image;
+    assert_eq!(image.get_pixel(0, 0), Rgba([1, 2, 3, 255]));
+    for color in [Rgba([4, 5, 6, 255]), Rgba([7, 8, 9, 255])]
+    {
+        assert!(image.pixels().any(|(_, _, pixel)| pixel == color), "{:?} is missing from the chart", color);
+    }
+    assert!(image.pixels().all(|(_, _, pixel)| pixel[3] == 255));
+    let padded = with_padding(&image, &theme);
+    assert_eq!(padded.dimensions(), (CHART_SIZE.0 as u32 + 6, CHART_SIZE.1 as u32 + 6));
+    assert_eq!(padded.get_pixel(0, 0), Rgba([1, 2, 3, 255]));
+    let height = cell_height(&config, &theme);
+    config.theme = Themes::Default;
+    assert_eq!(height, cell_height(&config, &config.theme.resolve()) + 6);
+}
+
 /// Prints the bits that make up other images
 #[test]
 pub fn output_images()
@@ -38,7 +284,7 @@ pub fn output_images()
 
     write_images!
     (
-        SEPARATOR, A, B, C, D, E, F, G, FLAT, FRONT_KEY, FRONT_KEY_OFF,
+        SEPARATOR, A, B, C, D, E, F, G, FLAT, SHARP, FRONT_KEY, FRONT_KEY_OFF,
         FRONT_F_KEY, FRONT_F_KEY_OFF,PALM_KEY, PALM_KEY_OFF, OCTAVE_KEY, 
         OCTAVE_KEY_OFF, BIS_KEY, BIS_KEY_OFF, LOW_A_KEY, LOW_A_KEY_OFF, 
         SIDE_KEY, SIDE_KEY_OFF, HIGH_F_SHARP_KEY, HIGH_F_SHARP_KEY_OFF, 
@@ -46,4 +292,804 @@ pub fn output_images()
         LOW_C_KEY, LOW_C_KEY_OFF, G_SHARP_KEY, G_SHARP_KEY_OFF, SMALL_PINKY_KEY, 
         SMALL_PINKY_KEY_OFF, LOW_B_FLAT_KEY, LOW_B_FLAT_KEY_OFF
     );
-}
\ No newline at end of file
+}
+
+/// Measures follow time signature changes
+#[test]
+fn bar_lines()
+{
+    use midly::MetaMessage::TimeSignature;
+
+    let mut events = vec![(0, TrackEventKind::Meta(TimeSignature(3, 2, 24, 8)))];
+    for i in 0..6
+    {
+        events.push((0, note_event(true, 60 + i, 64)));
+        events.push((480, note_event(false, 60 + i, 0)));
+    }
+    events.push((0, TrackEventKind::Meta(TimeSignature(6, 3, 24, 8))));
+    events.push((960, note_event(true, 60, 64)));
+    events.push((1440, note_event(false, 60, 0)));
+    let path = write_midi("bar_lines", &events);
+    let song = load_song(&path, &test_config());
+    let track = song.tracks().next().expect("Track is missing");
+    assert_eq!(track.bars, vec![0, 1440, 2880, 4320]);
+
+    // Time signatures with measures too long to count are left out with a warning
+    let path = write_midi("bad_time_signature", &[
+        (0, TrackEventKind::Meta(TimeSignature(4, 40, 24, 8))),
+        (0, note_event(true, 60, 64)),
+        (3840, note_event(false, 60, 0))
+    ]);
+    let song = load_song(&path, &test_config());
+    assert_eq!(song.tracks().next().expect("Track is missing").bars, vec![0, 1920]);
+    assert_eq!(song.report.warnings, vec![String::from("Time signature 4/2^40 at tick 0 is too long, ignoring it")]);
+}
+
+/// Songs are shifted by octave to fit into range, anything that still doesn't fit is folded by octave
+#[test]
+fn auto_transpose()
+{
+    let mut config = test_config();
+    config.auto_transpose = true;
+    let bytes = |name: &str, keys: &[u8]|
+    {
+        let events: Vec<_> = keys
+            .iter()
+            .flat_map(|&key| vec![(0, note_event(true, key, 64)), (480, note_event(false, key, 0))])
+            .collect();
+        let song = load_song(&write_midi(name, &events), &config);
+        let track = song.tracks().next().expect("Track is missing");
+        track.notes().iter().map(|note| note.note.byte).collect::<Vec<u8>>()
+    };
+    assert_eq!(bytes("auto_transpose_octave", &[84, 85, 86]), vec![72, 73, 74]);
+    assert_eq!(bytes("auto_transpose_fold", &[40, 60, 90]), vec![52, 72, 66]);
+
+    // The shift is reported to the caller, along with the notes which had to be folded
+    use crate::song::AutoTransposition;
+    let report = &load_song(&format!("{}/auto_transpose_octave.mid", OUTPUT_DIR), &config).report;
+    assert_eq!(report.transposition, Some(AutoTransposition { low: 84, high: 86, shift: -12 }));
+    assert!(report.warnings.is_empty());
+    let report = &load_song(&format!("{}/auto_transpose_fold.mid", OUTPUT_DIR), &config).report;
+    assert_eq!(report.warnings, vec!["Note out of range, folded by octave: 102 -> 66"]);
+    config.auto_transpose = false;
+    assert_eq!(load_song(&format!("{}/auto_transpose_octave.mid", OUTPUT_DIR), &config).report.transposition, None);
+}
+
+/// The optimal selector finds the cheapest path through every combination of fingerings
+#[test]
+fn optimal_fingerings()
+{
+    use crate::{FingeringSelection, cost::CostModel, note::Fingering, track::{Track, TrackNote}};
+
+    let bytes = [70, 72, 70, 73, 58, 60, 66, 58];
+    let track = Track
+    {
+        name: String::from("Test"),
+        instrument: None,
+        metadata: Default::default(),
+        channels: vec![0],
+        notes: bytes
+            .iter()
+            .map(|&byte| TrackNote { note: NOTES.get(byte).expect("Note is missing").clone(), start: 0, duration: 0, rest: 0, spelling: None, fingering: None })
+            .collect(),
+        ticks_per_beat: 480,
+        bars: vec![0]
+    };
+    let costs = CostModel::default();
+    let cost = |path: &[&Fingering]| path.windows(2).map(|pair| costs.transition(pair[0], pair[1])).sum::<f32>();
+
+    // Try every combination of fingerings
+    let mut cheapest = f32::MAX;
+    let mut path: Vec<&Fingering> = Vec::new();
+    fn search<'a>(notes: &'a [TrackNote], path: &mut Vec<&'a Fingering>, cheapest: &mut f32, cost: &dyn Fn(&[&Fingering]) -> f32)
+    {
+        match notes.split_first()
+        {
+            None => *cheapest = (*cheapest).min(cost(path)),
+            Some((note, rest)) => for fingering in &note.note.fingerings
+            {
+                path.push(fingering);
+                search(rest, path, cheapest, cost);
+                path.pop();
+            }
+        }
+    }
+    search(track.notes(), &mut path, &mut cheapest, &cost);
+
+    let optimal = track.select_fingerings(FingeringSelection::Optimal, &costs);
+    let greedy = track.select_fingerings(FingeringSelection::Greedy, &costs);
+    assert_eq!(optimal.len(), bytes.len());
+    assert_eq!(cost(&optimal), cheapest);
+    assert!(cost(&optimal) <= cost(&greedy));
+}
+
+/// Transition costs follow key weights, finger groups and preferences from the config
+#[test]
+fn transition_costs()
+{
+    use crate::{cost::CostModel, keys::Key::{self, *}, note::{Fingering, FingeringConfig}};
+    use enumset::EnumSet;
+
+    let fingering = |keys: EnumSet<Key>, preferred: bool| Fingering::new(&FingeringConfig { keys, preferred, name: None }, 60, &CONFIG, &CONFIG.theme.resolve());
+    let default = CostModel::default();
+    assert_eq!(default.transition(&fingering(Left1 | Left2, false), &fingering(Left1.into(), false)), 0.0);
+
+    let costs: CostModel = ron::de::from_str("(
+        key_weights: { Octave: 0.5 },
+        fingers: [(keys: [LowB, LowCsharp], penalty: 3)],
+        preferred_bonus: 2
+    )").expect("Failed to parse cost model");
+    assert_eq!(costs.transition(&fingering(Octave | Left1, false), &fingering(Left1.into(), false)), -0.5);
+    assert_eq!(costs.transition(&fingering(LowB.into(), false), &fingering(LowCsharp.into(), false)), 5.0);
+    assert_eq!(costs.transition(&fingering(LowB.into(), false), &fingering(LowB.into(), true)), -3.0);
+}
+
+/// Vector charts are laid out exactly like raster charts
+#[test]
+fn svg_layout()
+{
+    use image::GenericImageView;
+
+    let config = &*CONFIG;
+    let mut events = Vec::new();
+    for key in 46..78
+    {
+        events.push((120, note_event(true, key, 64)));
+        events.push((240 * (key as u32 % 3 + 1), note_event(false, key, 0)));
+    }
+    let song = load_song(&write_midi("svg_layout", &events), &test_config());
+    let track = song.tracks().next().expect("Track is missing");
+    let image = track.track_image(config, crate::Size::Scale(1));
+    let svg = track.track_svg(config);
+    assert_eq!((svg.width, svg.height), image.dimensions());
+    svg.save(&format!("{}/svg_layout.svg", OUTPUT_DIR), 2).expect("Failed to save svg");
+}
+
+/// Charts are scaled up by whole numbers as they are composed, with a size for each output format
+#[test]
+fn output_sizes()
+{
+    use crate::{OutputFormat, Size, track::row_width};
+    use image::GenericImageView;
+
+    assert_eq!(Size::Scale(6).scale(100), 6);
+    assert_eq!(Size::Width(300).scale(70), 4);
+    assert_eq!(Size::Width(10).scale(70), 1);
+    assert_eq!(Size::Dpi(300).scale(70), 4);
+    assert_eq!(Size::Dpi(10).scale(70), 1);
+
+    std::fs::create_dir_all(OUTPUT_DIR).expect("Failed to create test output dir");
+    let path = format!("{}/output_sizes.ron", OUTPUT_DIR);
+    std::fs::write(&path, "Config(scale: 1, sizes: (rows: Scale(6), tracks: Width(500)))").expect("Failed to write config");
+    let config = crate::layer::load(Some(&path)).expect("Failed to load config");
+    assert_eq!(config.size(OutputFormat::Separate), Size::Scale(1));
+    assert_eq!(config.size(OutputFormat::Rows), Size::Scale(6));
+    assert_eq!(config.size(OutputFormat::Tracks), Size::Width(500));
+
+    let events: Vec<_> = (60..64).flat_map(|key| vec![(0, note_event(true, key, 64)), (240, note_event(false, key, 0))]).collect();
+    let song = load_song(&write_midi("output_sizes", &events), &test_config());
+    let track = song.tracks().next().expect("Track is missing");
+    let config = test_config();
+    let image = track.track_image(&config, Size::Scale(1));
+    let tripled = track.track_image(&config, Size::Scale(3));
+    assert_eq!(tripled.dimensions(), (image.width() * 3, image.height() * 3));
+    assert!(image.pixels().all(|(x, y, pixel)| (0..3).all(|i| tripled.get_pixel(x * 3 + i, y * 3 + 2 - i) == pixel)));
+    let fitted = track.track_image(&config, Size::Width(image.width() * 5 - 1));
+    assert_eq!(fitted.width(), image.width() * 4);
+
+    let rows = track.rows(&config);
+    let row_images = track.row_images(&config, Size::Scale(2));
+    assert_eq!(row_images.len(), rows.len());
+    for (cells, row_image) in rows.iter().zip(&row_images)
+    {
+        assert_eq!(row_image.width(), row_width(cells, &config, &config.theme.resolve()) * 2);
+    }
+
+}
+
+/// Track images are the header followed by each row image, drawn onto one image of the size worked out up front
+#[test]
+fn composition()
+{
+    use crate::{Size, track::header_size};
+    use image::GenericImageView;
+
+    let events: Vec<_> = (50..75).flat_map(|key| vec![(0, note_event(true, key, 64)), (240, note_event(false, key, 0))]).collect();
+    let song = load_song(&write_midi("composition", &events), &test_config());
+    let track = song.tracks().next().expect("Track is missing");
+    let mut config = test_config();
+    config.show_title = true;
+    config.notes_per_row = 7;
+    let spacing = config.spacing as u32;
+    let image = track.track_image(&config, Size::Scale(2));
+    let rows = track.row_images(&config, Size::Scale(2));
+    assert_eq!(rows.len(), 4);
+    let mut y = (spacing + header_size(&track.header(&config), config.spacing).1) * 2;
+    for row in &rows
+    {
+        assert!(row.pixels().all(|(x, row_y, pixel)| image.get_pixel(x, y + row_y) == pixel));
+        y += row.height() + spacing * 2;
+    }
+    assert_eq!(image.height(), y);
+    assert_eq!(image.width(), rows.iter().map(|row| row.width()).max().unwrap_or(0));
+}
+
+/// Rows are paginated without splitting them, and each track starts on a new page
+#[test]
+fn pdf_pages()
+{
+    use crate::pdf::{PdfConfig, PdfTrack, write_pdf};
+
+    std::fs::create_dir_all(OUTPUT_DIR).expect("Failed to create test output dir");
+    let track = |name: &str, rows: usize| PdfTrack
+    {
+        name: name.to_string(),
+        rows: (0..rows).map(|_| image::DynamicImage::new_rgb8(500, 300)).collect()
+    };
+    let config = PdfConfig::default();
+
+    // Two 300pt rows fit within the height of an A4 page
+    let path = format!("{}/pdf_pages.pdf", OUTPUT_DIR);
+    write_pdf(&path, "Song", &[track("Melody", 5)], 10, &config).expect("Failed to save pdf");
+    let pdf = String::from_utf8_lossy(&std::fs::read(&path).expect("Failed to read pdf")).into_owned();
+    assert!(pdf.starts_with("%PDF"));
+    assert!(pdf.contains("/Count 3"));
+    assert!(pdf.contains("(Song - Melody) Tj"));
+    assert!(pdf.contains("(Page 3 of 3) Tj"));
+
+    let path = format!("{}/pdf_pages_all.pdf", OUTPUT_DIR);
+    write_pdf(&path, "Song", &[track("Melody", 1), track("Bass", 3)], 10, &config).expect("Failed to save pdf");
+    let pdf = String::from_utf8_lossy(&std::fs::read(&path).expect("Failed to read pdf")).into_owned();
+    assert!(pdf.contains("/Count 3"));
+    assert!(pdf.contains("(Song - Bass) Tj"));
+    assert!(pdf.contains("(Page 3 of 3) Tj"));
+}
+
+/// Command line options override values from the config file
+#[test]
+#[cfg(feature = "cli")]
+fn cli_overrides()
+{
+    use clap::Parser;
+
+    let args = crate::cli::Args::try_parse_from([
+        "sax-fingering-chart", "--format", "Pdf", "-t", "alto", "-n", "4", "--scale", "3", "--tracks", "0,2",
+        "--track-names", "sax,lead*", "--channels", "1,10", "--include-percussion", "song.mid"
+    ]).expect("Failed to parse arguments");
+    let mut config = crate::layer::load(args.config.as_deref()).expect("Failed to load config");
+    let spacing = config.spacing;
+    args.apply(&mut config);
+    assert!(config.output_format == crate::OutputFormat::Pdf);
+    assert_eq!(config.transposition_type as i16, crate::TranspositionType::Alto as i16);
+    assert_eq!(config.notes_per_row, 4);
+    assert_eq!(config.scale, 3);
+    assert_eq!(config.tracks, vec![0, 2]);
+    assert_eq!(config.track_names, vec!["sax", "lead*"]);
+    assert_eq!(config.channels, vec![1, 10]);
+    assert!(!config.skip_percussion);
+    assert_eq!(config.spacing, spacing);
+    assert_eq!(args.files, vec!["song.mid"]);
+
+    let mut config = crate::layer::load(None).expect("Failed to load config");
+    crate::cli::Args::try_parse_from(["sax-fingering-chart", "--width", "800", "song.mid"]).expect("Failed to parse arguments").apply(&mut config);
+    assert_eq!(config.size(crate::OutputFormat::Separate), crate::Size::Width(800));
+    assert_eq!(config.size(crate::OutputFormat::Tracks), crate::Size::Width(800));
+
+    assert!(crate::cli::Args::try_parse_from(["sax-fingering-chart", "--scale", "0", "song.mid"]).is_err());
+    assert!(crate::cli::Args::try_parse_from(["sax-fingering-chart", "--scale", "2", "--dpi", "300", "song.mid"]).is_err());
+    assert!(crate::cli::Args::try_parse_from(["sax-fingering-chart", "-n", "0", "song.mid"]).is_err());
+    assert!(crate::cli::Args::try_parse_from(["sax-fingering-chart", "--channels", "17", "song.mid"]).is_err());
+    assert!(crate::cli::Args::try_parse_from(["sax-fingering-chart"]).is_err());
+}
+
+/// User configs only replace the fields and notes they list, everything else comes from the default config
+#[test]
+fn config_layers()
+{
+    std::fs::create_dir_all(OUTPUT_DIR).expect("Failed to create test output dir");
+    let path = format!("{}/config_layers.ron", OUTPUT_DIR);
+    std::fs::write(&path, "Config(transposition_type: Alto, notes: { 60: [[Left2]] })").expect("Failed to write config");
+
+    let default: crate::Config = ron::de::from_str(crate::layer::DEFAULT_CONFIG).expect("Failed to parse default config");
+    let config = crate::layer::load(Some(&path)).expect("Failed to load config");
+    assert_eq!(config.transposition_type as i16, crate::TranspositionType::Alto as i16);
+    assert_eq!(config.notes_per_row, default.notes_per_row);
+    assert_eq!(config.notes.len(), default.notes.len());
+    assert_eq!(config.notes[&60].len(), 1);
+    assert_eq!(config.notes[&60][0].keys, enumset::enum_set!(crate::keys::Key::Left2));
+    assert_eq!(config.notes[&62].len(), default.notes[&62].len());
+
+    assert!(crate::layer::load(Some("test_out/missing.ron")).is_err());
+}
+
+/// Failures while loading songs and notes are reported as errors
+#[test]
+fn load_errors()
+{
+    use crate::Error;
+
+    let config = test_config();
+    let missing = Song::load("test_out/missing.mid", &NOTES, &config);
+    assert!(matches!(missing, Err(Error::Io(_))));
+
+    std::fs::create_dir_all(OUTPUT_DIR).expect("Failed to create test output dir");
+    let path = format!("{}/load_errors.mid", OUTPUT_DIR);
+    std::fs::write(&path, b"not a midi file").expect("Failed to write midi file");
+    assert!(matches!(Song::load(&path, &NOTES, &config), Err(Error::Midi(_))));
+
+    // Tracks whose events add up to more ticks than can be counted are an error rather than an overflow
+    let events: Vec<_> = (0..17).map(|_| (0x0FFF_FFFF, note_event(true, 60, 64))).collect();
+    let path = write_midi("too_long", &events);
+    assert!(matches!(Song::load(&path, &NOTES, &config), Err(Error::Score(_))));
+
+    let mut config = test_config();
+    config.notes.insert(60, Vec::new());
+    assert!(matches!(Notes::new(&config), Err(Error::NoFingerings(60))));
+
+    let mut config = test_config();
+    config.notes_per_row = 0;
+    assert!(matches!(Notes::new(&config), Err(Error::NoNotesPerRow)));
+}
+
+/// Chords are reduced to a single note or split into voices
+#[test]
+fn polyphony()
+{
+    use crate::Polyphony;
+
+    // A C major chord with the loudest note in the middle, a lone D, then a slightly staggered F and A
+    let path = write_midi("polyphony", &[
+        (0, note_event(true, 60, 50)),
+        (0, note_event(true, 64, 100)),
+        (0, note_event(true, 67, 50)),
+        (480, note_event(false, 60, 0)),
+        (0, note_event(false, 64, 0)),
+        (0, note_event(false, 67, 0)),
+        (0, note_event(true, 62, 64)),
+        (480, note_event(false, 62, 0)),
+        (0, note_event(true, 65, 64)),
+        (5, note_event(true, 69, 64)),
+        (475, note_event(false, 65, 0)),
+        (0, note_event(false, 69, 0))
+    ]);
+    let voices = |polyphony: Polyphony|
+    {
+        let mut config = test_config();
+        config.polyphony = polyphony;
+        load_song(&path, &config)
+            .tracks()
+            .map(|track| track.notes().iter().map(|note| note.note.byte).collect::<Vec<u8>>())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(voices(Polyphony::Keep), vec![vec![60, 64, 67, 62, 65, 69]]);
+    assert_eq!(voices(Polyphony::Highest), vec![vec![67, 62, 69]]);
+    assert_eq!(voices(Polyphony::Lowest), vec![vec![60, 62, 65]]);
+    assert_eq!(voices(Polyphony::Loudest), vec![vec![64, 62, 69]]);
+    assert_eq!(voices(Polyphony::Split), vec![vec![67, 62, 69], vec![64, 65], vec![60]]);
+
+    // Reduced notes are reported to the caller rather than printed
+    let mut config = test_config();
+    config.polyphony = Polyphony::Split;
+    let report = &load_song(&path, &config).report;
+    assert_eq!((report.reduced_notes, report.extra_voices), (3, 2));
+    config.polyphony = Polyphony::Keep;
+    assert_eq!(load_song(&path, &config).report.reduced_notes, 0);
+}
+
+/// Notes are split into tracks by channel or program, and tracks are named after their instrument
+#[test]
+fn split_tracks()
+{
+    use crate::SplitBy;
+
+    let program = |channel: u8, program: u8| TrackEventKind::Midi
+    {
+        channel: channel.into(),
+        message: MidiMessage::ProgramChange { program: program.into() }
+    };
+    // An alto sax and an unnamed channel, then the sax channel switches to a piano
+    let path = write_midi("split_tracks", &[
+        (0, program(0, 65)),
+        (0, channel_event(0, true, 60, 64)),
+        (0, channel_event(2, true, 64, 64)),
+        (480, channel_event(0, false, 60, 0)),
+        (0, channel_event(2, false, 64, 0)),
+        (0, program(0, 0)),
+        (0, channel_event(0, true, 62, 64)),
+        (480, channel_event(0, false, 62, 0))
+    ]);
+    let tracks = |split_by: SplitBy|
+    {
+        let mut config = test_config();
+        config.split_by = split_by;
+        load_song(&path, &config)
+            .tracks()
+            .map(|track| (track.name.clone(), track.file_name(), track.notes().iter().map(|note| note.note.byte).collect::<Vec<u8>>()))
+            .collect::<Vec<_>>()
+    };
+    let track = |name: &str, file_name: &str, bytes: &[u8]| (name.to_string(), file_name.to_string(), bytes.to_vec());
+    assert_eq!(tracks(SplitBy::Track), vec![track("Alto Sax", "alto_sax", &[60, 64, 62])]);
+    assert_eq!(tracks(SplitBy::Channel), vec![track("Alto Sax", "alto_sax", &[60, 62]), track("Channel 3", "channel_3", &[64])]);
+    assert_eq!(tracks(SplitBy::Program), vec![
+        track("Acoustic Grand Piano", "acoustic_grand_piano", &[62]),
+        track("Alto Sax", "alto_sax", &[60]),
+        track("Channel 3", "channel_3", &[64])
+    ]);
+}
+
+/// Track names, instrument names, the song title and copyright are read from meta events
+#[test]
+fn metadata()
+{
+    use midly::MetaMessage::{TrackName, InstrumentName, Copyright};
+
+    let meta = |message| TrackEventKind::Meta(message);
+    let path = write_midi("metadata", &[
+        (0, meta(TrackName(b"Blue Bossa"))),
+        (0, meta(Copyright(b"(c) 1963 "))),
+        (0, meta(InstrumentName(b"Tenor Sax"))),
+        (0, note_event(true, 60, 64)),
+        (480, note_event(false, 60, 0))
+    ]);
+    let mut config = test_config();
+    let song = load_song(&path, &config);
+    assert_eq!(song.metadata.title.as_deref(), Some("Blue Bossa"));
+    assert_eq!(song.metadata.copyright.as_deref(), Some("(c) 1963"));
+    let track = song.tracks().next().expect("Song has no tracks");
+    assert_eq!((track.name.as_str(), track.file_name().as_str()), ("Tenor Sax", "tenor_sax"));
+    config.show_title = false;
+    assert!(track.header(&config).is_empty());
+    let plain = track.track_image(&config, crate::Size::Scale(1));
+    config.show_title = true;
+    assert_eq!(track.header(&config), vec!["Blue Bossa - Tenor Sax", "(c) 1963"]);
+    let titled = track.track_image(&config, crate::Size::Scale(1));
+    assert!(titled.height() > plain.height());
+    titled.save(format!("{}/metadata.png", OUTPUT_DIR)).expect("Failed to save image");
+
+    // Unnamed tracks are numbered as in the midi file, even when tracks before them are empty
+    let path = write_midi_tracks("track_numbers", Format::Parallel, &[
+        &[(0, meta(TrackName(b"Conductor")))],
+        &[],
+        &[(0, note_event(true, 60, 64)), (480, note_event(false, 60, 0))],
+        &[(0, meta(TrackName(b"Lead / Melody"))), (0, note_event(true, 62, 64)), (480, note_event(false, 62, 0))]
+    ]);
+    let song = load_song(&path, &test_config());
+    assert_eq!(song.metadata.title.as_deref(), Some("Conductor"));
+    let names: Vec<(String, String)> = song.tracks().map(|track| (track.name.clone(), track.file_name())).collect();
+    assert_eq!(names, vec![
+        ("Track 3".to_string(), "track_3".to_string()),
+        ("Lead / Melody".to_string(), "lead_melody".to_string())
+    ]);
+}
+
+/// Tracks are selected by number, name and channel, and percussion and short tracks are skipped
+#[test]
+fn track_selection()
+{
+    use midly::MetaMessage::TrackName;
+
+    let notes = |channel: u8, name: &'static [u8], count: u32| -> Vec<(u32, TrackEventKind<'static>)>
+    {
+        let mut events = vec![(0, TrackEventKind::Meta(TrackName(name)))];
+        for i in 0..count
+        {
+            events.push((0, channel_event(channel, true, 60 + i as u8, 64)));
+            events.push((240, channel_event(channel, false, 60 + i as u8, 0)));
+        }
+        events
+    };
+    let path = write_midi_tracks("track_selection", Format::Parallel, &[
+        &[],
+        &notes(0, b"Lead Alto Sax", 8),
+        &notes(9, b"Drums", 8),
+        &notes(1, b"Tenor Sax", 8),
+        &notes(2, b"Bass", 2)
+    ]);
+    let song = load_song(&path, &test_config());
+    let selected = |apply: &dyn Fn(&mut Config)|
+    {
+        let mut config = test_config();
+        config.skip_percussion = false;
+        config.min_notes = 0;
+        apply(&mut config);
+        song.selected_tracks(&config).iter().map(|track| track.name.clone()).collect::<Vec<_>>()
+    };
+    assert_eq!(selected(&|_| ()), vec!["Lead Alto Sax", "Drums", "Tenor Sax", "Bass"]);
+    assert_eq!(selected(&|config| config.tracks = vec![1, 3]), vec!["Drums", "Bass"]);
+    assert_eq!(selected(&|config| config.track_names = vec![String::from("SAX")]), vec!["Lead Alto Sax", "Tenor Sax"]);
+    assert_eq!(selected(&|config| config.track_names = vec![String::from("t?n*sax")]), vec!["Tenor Sax"]);
+    assert_eq!(selected(&|config| config.channels = vec![2, 3]), vec!["Tenor Sax", "Bass"]);
+    assert_eq!(selected(&|config| config.skip_percussion = true), vec!["Lead Alto Sax", "Tenor Sax", "Bass"]);
+    assert_eq!(selected(&|config| config.min_notes = 4), vec!["Lead Alto Sax", "Drums", "Tenor Sax"]);
+    // Tracks must pass every filter
+    assert_eq!(selected(&|config|
+    {
+        config.channels = vec![1, 10];
+        config.track_names = vec![String::from("sax")];
+    }), vec!["Lead Alto Sax"]);
+
+    // Skipped tracks are given to the caller along with the reason
+    let mut config = test_config();
+    config.skip_percussion = true;
+    config.min_notes = 4;
+    let skipped: Vec<_> = song.skipped_tracks(&config).into_iter().map(|(i, track, reason)| (i, track.name.clone(), reason)).collect();
+    assert_eq!(skipped, vec![
+        (1, String::from("Drums"), String::from("percussion (channel 10)")),
+        (3, String::from("Bass"), String::from("only 2 note(s), fewer than 4"))
+    ]);
+}
+
+/// MusicXML parts become tracks with their written spelling, ties and measures kept
+#[test]
+fn musicxml()
+{
+    use crate::note::{Accidental, Spelling};
+    assert_eq!(crate::song::InputFormat::from_path("song.MXL"), crate::song::InputFormat::MusicXml);
+    assert_eq!(crate::song::InputFormat::from_path("song.mid"), crate::song::InputFormat::Midi);
+    let mut config = test_config();
+    config.transposition_type = crate::TranspositionType::Alto;
+    config.min_notes = 0;
+    let spelled = |letter, accidental| Some(Spelling { letter, accidental });
+    for path in ["src/tests/spelling.musicxml", "src/tests/spelling.mxl"]
+    {
+        let song = load_song(path, &config);
+        assert_eq!(song.metadata.title.as_deref(), Some("Spelling Test"));
+        assert_eq!(song.metadata.copyright.as_deref(), Some("Public domain"));
+        let track = song.tracks().next().expect("Track is missing");
+        assert_eq!(track.name, "Alto Saxophone");
+        assert_eq!(track.bars, vec![0, 3840]);
+        // Written pitches are taken to concert pitch by the part's transposition, then to the chosen sax
+        let notes: Vec<_> = track.notes.iter().map(|note| (note.note.byte, note.start, note.duration, note.spelling)).collect();
+        assert_eq!(notes, vec![
+            (54, 0, 960, spelled('F', Accidental::Sharp)),
+            (54, 960, 960, spelled('G', Accidental::Flat)),
+            (60, 1920, 2880, spelled('C', Accidental::Natural)),
+            (58, 5760, 1920, spelled('B', Accidental::Flat)),
+            (62, 5760, 1920, spelled('D', Accidental::Natural))
+        ]);
+    }
+
+    // Damaged archives are reported as errors
+    std::fs::create_dir_all(OUTPUT_DIR).expect("Failed to create test output dir");
+    let archive = std::fs::read("src/tests/spelling.mxl").expect("Failed to read archive");
+    let damaged = [("truncated", archive[..archive.len() / 2].to_vec()), ("garbage", b"PK\x05\x06 not a zip file".to_vec()), ("empty", Vec::new())];
+    for (name, data) in damaged
+    {
+        let path = format!("{}/{}.mxl", OUTPUT_DIR, name);
+        std::fs::write(&path, data).expect("Failed to write archive");
+        assert!(crate::musicxml::read(&path, 0).is_err(), "{} archive was read", name);
+    }
+
+    // Pitches and durations too large to count are reported as errors
+    let score = |note: &str| format!(
+        "<score-partwise><part id=\"P1\"><measure><attributes><divisions>1</divisions></attributes>{}</measure></part></score-partwise>", note);
+    let note = |pitch: &str, duration: &str| format!("<note><pitch>{}</pitch><duration>{}</duration></note>", pitch, duration);
+    assert!(crate::musicxml::parse(&score(&note("<step>C</step><octave>4</octave>", "1")), 0).is_ok());
+    assert!(crate::musicxml::parse(&score(&note("<step>C</step><octave>3000</octave>", "1")), 0).is_err());
+    assert!(crate::musicxml::parse(&score(&note("<step>C</step><alter>40000</alter><octave>4</octave>", "1")), 0).is_err());
+    let long_note = note("<step>C</step><octave>4</octave>", "4000000");
+    assert!(crate::musicxml::parse(&score(&long_note.repeat(2)), 0).is_err());
+
+    // A backup between a tie's start and stop leaves the stop before the tied note, so it starts a new note
+    let tied = |tie: &str, duration: &str| format!(
+        "<note><pitch><step>C</step><octave>4</octave></pitch><duration>{}</duration><tie type=\"{}\"/></note>", duration, tie);
+    let part = format!("{}{}<backup><duration>4</duration></backup>{}", note("<step>D</step><octave>4</octave>", "2"), tied("start", "1"), tied("stop", "1"));
+    let raw = crate::musicxml::parse(&score(&part), 0).expect("Failed to parse tie after backup");
+    let notes: Vec<_> = raw.tracks[0].iter().map(|note| (note.key, note.start, note.duration)).collect();
+    assert_eq!(notes, vec![(62, 0, 1920), (60, 0, 960), (60, 1920, 960)]);
+}
+
+/// Notes without a written spelling are spelled in the key signature from the midi file, the config or detected from
+/// the notes, moved into the key the notes are transposed to
+#[test]
+fn key_signatures()
+{
+    use crate::{KeySignatures, Note, note::KeySignature};
+    use midly::MetaMessage;
+
+    let names = |bytes: &[u8]| bytes.iter().map(|&byte| Note::spelling(byte).to_string()).collect::<Vec<_>>();
+    assert_eq!(names(&[61, 63, 66, 68, 70]), vec!["Db", "Eb", "Gb", "Ab", "Bb"]);
+
+    let key = |text: &str| text.parse::<KeySignature>().expect("Failed to parse key");
+    assert_eq!(key("D"), KeySignature { sharps: 2, minor: false });
+    assert_eq!(key("Bb major"), KeySignature { sharps: -2, minor: false });
+    assert_eq!(key("F#m"), KeySignature { sharps: 3, minor: true });
+    assert_eq!(key("Cb"), KeySignature { sharps: -7, minor: false });
+    assert!("G#".parse::<KeySignature>().is_err());
+    assert!("H".parse::<KeySignature>().is_err());
+    assert!(format!("F{}", "b".repeat(22)).parse::<KeySignature>().is_err());
+    assert!(format!("F{}m", "b".repeat(18)).parse::<KeySignature>().is_err());
+    assert!(format!("B{}", "#".repeat(22)).parse::<KeySignature>().is_err());
+    assert_eq!(key("F#m").to_string(), "F# minor");
+    assert_eq!(key("Eb").transposed(-3).to_string(), "C major");
+    assert_eq!(key("E").transposed(-3).to_string(), "Db major");
+    assert_eq!(key("C#").transposed(12), key("C#"));
+
+    let spell = |text: &str, bytes: &[i16]| bytes.iter().map(|&byte| key(text).spell(byte).to_string()).collect::<Vec<_>>();
+    assert_eq!(spell("D", &[61, 66, 60, 70]), vec!["C#", "F#", "C", "A#"]);
+    assert_eq!(spell("Bb", &[63, 66]), vec!["Eb", "Gb"]);
+    assert_eq!(spell("Am", &[68, 70]), vec!["G#", "Bb"]);
+    assert_eq!(spell("F#", &[65, 71]), vec!["E#", "B"]);
+    assert_eq!(spell("Gb", &[59]), vec!["Cb"]);
+    assert_eq!(KeySignature::detect([62, 64, 66, 67, 69, 71, 73, 74].iter().map(|&key| (key, 480))), key("D"));
+    assert_eq!(KeySignature::detect([69, 71, 72, 74, 76, 77, 80, 81, 69].iter().map(|&key| (key, 480))), key("Am"));
+
+    let path = write_midi("key_signatures", &[
+        (0, TrackEventKind::Meta(MetaMessage::KeySignature(2, false))),
+        (0, note_event(true, 66, 64)),
+        (480, note_event(false, 66, 0)),
+        (0, TrackEventKind::Meta(MetaMessage::KeySignature(-3, false))),
+        (0, note_event(true, 68, 64)),
+        (480, note_event(false, 68, 0))
+    ]);
+    let spellings = |config: &Config|
+    {
+        let song = load_song(&path, config);
+        let track = song.tracks().next().expect("Track is missing");
+        track.notes().iter().map(|note| note.spelling.expect("Note is not spelled").to_string()).collect::<Vec<_>>()
+    };
+    let mut config = test_config();
+    assert_eq!(spellings(&config), vec!["F#", "Ab"]);
+    config.key_signature = KeySignatures::Fixed(key("E"));
+    assert_eq!(spellings(&config), vec!["F#", "G#"]);
+    // F# and G# are both only in keys with at least 3 sharps, and F# is heard more than A
+    config.key_signature = KeySignatures::Detect;
+    assert_eq!(spellings(&config), vec!["F#", "G#"]);
+    assert_eq!(load_song(&path, &config).report.detected_key, Some(key("F#m")));
+    // On an alto, D major is written in B major and Eb major in C major
+    config.key_signature = KeySignatures::Auto;
+    config.transposition_type = crate::TranspositionType::Alto;
+    assert_eq!(spellings(&config), vec!["D#", "F"]);
+    assert_eq!(load_song(&path, &config).report.detected_key, None);
+}
+
+/// ABC voices become tracks with repeats played out, and the key signature and accidentals are applied
+#[test]
+fn abc()
+{
+    use crate::note::{Accidental, Spelling};
+    let song = load_song("src/tests/reel.abc", &test_config());
+    assert_eq!(song.metadata.title.as_deref(), Some("Test Reel"));
+    assert_eq!(song.metadata.text, vec!["Trad."]);
+    let tracks: Vec<_> = song.tracks().collect();
+    assert_eq!(tracks.iter().map(|track| track.name.as_str()).collect::<Vec<_>>(), vec!["Lead", "Harmony"]);
+    assert_eq!(tracks[0].bars, vec![0, 3840, 7680, 11520, 15360, 19200]);
+    // Tunes with several voices are still written to a directory named after their title
+    assert_eq!(song.output_name("src/tests/reel.abc"), "test_reel");
+    let notes = |track: &crate::track::Track| track.notes.iter().map(|note| (note.note.byte, note.start, note.duration)).collect::<Vec<_>>();
+    let first_time = |offset: u32| vec![
+        (67, offset, 480), (69, offset + 480, 480), (71, offset + 960, 480), (72, offset + 1440, 480),
+        (74, offset + 1920, 960), (73, offset + 2880, 960), (74, offset + 3840, 2880)
+    ];
+    let mut lead = first_time(0);
+    lead.extend([(71, 7680, 720), (72, 8400, 240), (74, 8640, 320), (76, 8960, 320), (74, 9280, 320), (72, 9600, 960)]);
+    lead.extend(first_time(11520));
+    lead.extend([(77, 19200, 960), (70, 20160, 960), (72, 21120, 1920)]);
+    assert_eq!(notes(tracks[0]), lead);
+    assert_eq!(notes(tracks[1]), vec![(55, 0, 3840), (62, 3840, 3840), (55, 7680, 3840), (55, 11520, 3840), (62, 15360, 3840), (55, 19200, 3840)]);
+    let spelling = |i: usize| tracks[0].notes[i].spelling;
+    assert_eq!(spelling(5), Some(Spelling { letter: 'C', accidental: Accidental::Sharp }));
+    assert_eq!(spelling(20), Some(Spelling { letter: 'F', accidental: Accidental::Natural }));
+    assert_eq!(spelling(21), Some(Spelling { letter: 'B', accidental: Accidental::Flat }));
+
+    // A tune with a single voice is named after its title, and the key signature applies to every octave
+    let raw = crate::abc::parse("X:1\nT:Scale\nL:1/4\nK:F\nFGAB|cb2z|]", 0).expect("Failed to parse tune");
+    assert_eq!(raw.names, vec![(Some(String::from("Scale")), None)]);
+    assert_eq!(raw.tracks[0].iter().map(|note| note.key).collect::<Vec<_>>(), vec![65, 67, 69, 70, 72, 82]);
+    assert!(crate::abc::parse("X:1\nT:No key\nCDEF|", 0).is_err());
+    assert!(crate::abc::parse("X:1\nK:C\nA99999999|", 0).is_err());
+    assert!(crate::abc::parse("X:1\nK:C\n[CE]99999999|", 0).is_err());
+    assert!(crate::abc::parse("X:1\nK:C\nA>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>B|", 0).is_err());
+    assert!(crate::abc::parse("X:1\nK:C\n(3A8000000BC|", 0).is_err());
+    let rest = crate::abc::parse("X:1\nK:C\nZ2|C|", 0).expect("Failed to parse multi-bar rest");
+    assert_eq!(rest.tracks[0][0].start, 7680);
+    assert!(crate::abc::parse("X:1\nK:C\nZ4000000000|", 0).is_err());
+    assert!(crate::abc::parse("X:1\nK:C\nZ400000000|", 0).is_err());
+    assert!(crate::abc::parse("X:1\nM:4000000/1\nK:C\nZ2|", 0).is_err());
+    assert!(crate::abc::parse("X:1\nM:2000000000/4\nK:C\nC|", 0).is_err());
+    assert!(crate::abc::parse("X:1\nM:4000000000+4000000000/4\nK:C\nC|", 0).is_err());
+    assert!(crate::abc::parse("X:1\nM:3/4000000000\nK:C\nC|", 0).is_err());
+    assert!(crate::abc::parse("X:1\nV:1 transpose=32767\nK:C\nC|", 0).is_err());
+    assert!(crate::abc::parse(&format!("X:1\nK:C\nc{}|", "'".repeat(3000)), 0).is_err());
+    assert!(crate::abc::parse(&format!("X:1\nK:C\n{}c|", "^".repeat(40000)), 0).is_err());
+    assert!(crate::abc::parse("X:1\nK:C\n^^^c|", 0).is_err());
+    let endings = crate::abc::parse("X:1\nK:C\n|:C|1 D:|2-4294967295 E|", 0).expect("Failed to parse endings");
+    assert_eq!(endings.tracks[0].iter().map(|note| note.key).collect::<Vec<_>>(), vec![60, 62, 60, 64]);
+    assert!(crate::abc::parse("X:1\nK:C\n__C,,,,,|", 0).is_ok());
+    assert!(crate::abc::parse("X:1\nV:1 transpose=-12\nK:C\nc|", 0).is_ok_and(|song| song.tracks[0][0].key == 60));
+    assert!(crate::abc::parse("X:1\nV:1 transpose=127\nK:C\nc|", i16::MAX).is_err());
+
+    // Only the first of several tunes is read, which is reported as a warning
+    let path = format!("{}/two_tunes.abc", OUTPUT_DIR);
+    std::fs::create_dir_all(OUTPUT_DIR).expect("Failed to create test output dir");
+    std::fs::write(&path, "X:1\nT:First\nK:C\nCDEF|\n\nX:2\nT:Second\nK:C\nGABc|\n").expect("Failed to write tunes");
+    let song = load_song(&path, &test_config());
+    assert_eq!(song.metadata.title.as_deref(), Some("First"));
+    assert_eq!(song.report.warnings, vec![format!("{}: reading the first of 2 tunes", path)]);
+    std::fs::write(&path, "X:1\nK:C\nCDEF|\n").expect("Failed to write tune");
+    assert_eq!(load_song(&path, &test_config()).output_name(&path), "two_tunes");
+}
+
+/// Note lists are read one beat per note, and pinned fingerings are used instead of selected ones
+#[test]
+fn note_list()
+{
+    use crate::note::{Accidental, Spelling};
+    assert_eq!(crate::song::InputFormat::from_path("-"), crate::song::InputFormat::Text);
+    let raw = crate::text::parse("Bb3 C4 | D4:1 r\nA#3|C4", 0).expect("Failed to parse note list");
+    let notes: Vec<_> = raw.tracks[0].iter().map(|note| (note.key, note.start, note.fingering)).collect();
+    assert_eq!(notes, vec![(58, 0, None), (60, 480, None), (62, 960, Some(1)), (58, 1920, None), (60, 2400, None)]);
+    assert_eq!(raw.bars, vec![0, 960, 2400]);
+    assert_eq!(raw.tracks[0][3].spelling, Some(Spelling { letter: 'A', accidental: Accidental::Sharp }));
+    assert!(crate::text::parse("C4 H4", 0).is_err());
+    assert!(crate::text::parse("C4:x", 0).is_err());
+    assert!(crate::text::parse("C3000", 0).is_err());
+    assert!(crate::text::parse("C-3000", 0).is_err());
+
+    // A pinned fingering overrides selection, whichever fingering the selection would have picked
+    let note = (46..78).filter_map(|byte| NOTES.get(byte)).find(|note| note.fingerings.len() > 1).expect("No note has alternate fingerings");
+    let name = format!("{}{}", crate::Note::spelling(note.byte), note.byte / 12 - 1);
+    let path = format!("{}/note_list.txt", OUTPUT_DIR);
+    std::fs::create_dir_all(OUTPUT_DIR).expect("Failed to create test output dir");
+    let mut config = test_config();
+    config.min_notes = 0;
+    for selection in [crate::FingeringSelection::Greedy, crate::FingeringSelection::Optimal]
+    {
+        config.fingering_selection = selection;
+        for pin in 0..note.fingerings.len()
+        {
+            std::fs::write(&path, format!("{0} {0}:{1} {0}", name, pin)).expect("Failed to write note list");
+            let song = load_song(&path, &config);
+            let track = song.tracks().next().expect("Track is missing");
+            let fingerings = track.select_fingerings(config.fingering_selection, &config.costs);
+            assert_eq!(fingerings.len(), 3);
+            assert!(std::ptr::eq(fingerings[1], &track.notes()[1].note.fingerings[pin]));
+        }
+    }
+}
+
+/// Exercises cover the note range from tonic to tonic, spelled to suit the scale and direction
+#[test]
+fn exercises()
+{
+    use crate::exercise::{Exercise, Pattern, ScaleKind};
+    let exercise = |spec: &str| spec.parse::<Exercise>().unwrap_or_else(|e| panic!("Failed to parse {}: {}", spec, e));
+    let names = |spec: &str| exercise(spec).keys(&NOTES).iter().map(|(_, spelling)| spelling.map_or(String::from("?"), |spelling| spelling.to_string())).collect::<Vec<_>>();
+    let keys = |spec: &str| exercise(spec).keys(&NOTES).iter().map(|&(key, _)| key).collect::<Vec<_>>();
+
+    let parsed = exercise("F# harmonic minor thirds");
+    assert_eq!((parsed.tonic.to_string(), parsed.scale, parsed.pattern), (String::from("F#"), ScaleKind::HarmonicMinor, Pattern::Thirds));
+    assert_eq!(exercise("Bb").name(), "Bb major scale");
+    assert!("H major".parse::<Exercise>().is_err());
+    assert!("C majestic".parse::<Exercise>().is_err());
+
+    // Scales start and end on the lowest and highest tonic in range
+    let low = (0..=127).find(|&byte| NOTES.get(byte).is_some()).expect("No notes") as i16;
+    let scale = keys("C major ascending");
+    let (first, last) = (scale[0] as i16, *scale.last().expect("Scale is empty") as i16);
+    assert_eq!(first % 12, 0);
+    assert_eq!(last % 12, 0);
+    assert!(first - low < 12);
+    let mut up_down = keys("C major ascending");
+    up_down.extend(keys("C major descending").into_iter().skip(1));
+    assert_eq!(keys("C major"), up_down);
+
+    assert_eq!(names("F# harmonic-minor ascending")[..8], ["F#", "G#", "A", "B", "C#", "D", "E#", "F#"]);
+    assert_eq!(names("A melodic-minor ascending")[..8], ["A", "B", "C", "D", "E", "F#", "G#", "A"]);
+    assert_eq!(names("A melodic-minor descending")[..8], ["A", "G", "F", "E", "D", "C", "B", "A"]);
+    assert_eq!(names("C chromatic ascending")[..4], ["C", "C#", "D", "D#"]);
+    assert_eq!(names("C chromatic descending")[..4], ["C", "B", "Bb", "A"]);
+    assert_eq!(names("C blues ascending")[..7], ["C", "Eb", "F", "Gb", "G", "Bb", "C"]);
+    assert_eq!(names("C arpeggio")[..4], ["C", "E", "G", "C"]);
+    assert_eq!(names("D dorian arpeggio")[..4], ["D", "F", "A", "D"]);
+    assert_eq!(names("C thirds")[..6], ["C", "E", "D", "F", "E", "G"]);
+
+    // Exercises go through the same track pipeline as songs
+    let mut config = test_config();
+    config.min_notes = 0;
+    let song = Song::exercise(&exercise("Eb mixolydian"), &NOTES, &config);
+    let track = song.tracks().next().expect("Track is missing");
+    assert_eq!(track.name, "Eb mixolydian scale");
+    assert_eq!(track.notes().iter().map(|note| note.note.byte).collect::<Vec<_>>(), keys("Eb mixolydian"));
+    assert_eq!(track.bars.len(), track.notes().len().div_ceil(4));
+}
@@ -0,0 +1,93 @@
+use crate::{Error, note::Spelling, song::{Metadata, RawNote, RawSong}};
+use std::{fs, io::{self, Read}};
+
+/// Ticks per beat used for notes read from a note list. Every note and rest is a beat long.
+const TICKS_PER_BEAT: u32 = 480;
+/// Velocity of every note in a note list
+const VELOCITY: u8 = 64;
+
+/// Read a note list from a file, or from standard input if the path is `-`. Pitches are moved by the given number of
+/// semitones.
+pub(crate) fn read(path: &str, transposition: i16) -> Result<RawSong, Error>
+{
+    let text = if path == "-"
+    {
+        let mut text = String::new();
+        io::stdin().read_to_string(&mut text)?;
+        text
+    }
+    else
+    {
+        fs::read_to_string(path)?
+    };
+    parse(&text, transposition)
+}
+
+/// Read a note list such as `Bb3 C4 | D4:1 r Eb4`, which becomes a single track. Notes are written as a letter, any
+/// number of flats (`b`) or sharps (`#`) and an octave from -1 to 9, where `C4` is middle C. A note may be followed by
+/// `:` and the index of the fingering it must use, counting from 0. Rests are written as `r` and bar lines as `|`.
+pub(crate) fn parse(text: &str, transposition: i16) -> Result<RawSong, Error>
+{
+    let mut notes = Vec::new();
+    let mut bars = vec![0];
+    let mut tick = 0;
+    for (number, line) in text.lines().enumerate()
+    {
+        for token in line.split('|').enumerate().flat_map(|(i, part)| (i > 0).then_some("|").into_iter().chain(part.split_whitespace()))
+        {
+            match token
+            {
+                "|" =>
+                {
+                    if bars.last() != Some(&tick)
+                    {
+                        bars.push(tick);
+                    }
+                },
+                "r" | "R" => (),
+                _ =>
+                {
+                    let invalid = || Error::Score(format!("line {}: invalid note '{}'", number + 1, token));
+                    let (written, spelling, fingering) = read_note(token).ok_or_else(invalid)?;
+                    let key = written.checked_add(transposition).ok_or_else(invalid)?;
+                    let spelling = spelling.and_then(|spelling| spelling.transposed(written, key));
+                    notes.push(RawNote { key, velocity: VELOCITY, channel: 0, program: None, start: tick, duration: TICKS_PER_BEAT, spelling, fingering });
+                }
+            }
+            if token != "|"
+            {
+                tick += TICKS_PER_BEAT;
+            }
+        }
+    }
+    Ok(RawSong { ticks_per_beat: TICKS_PER_BEAT, bars, metadata: Metadata::default(), tracks: vec![notes], names: vec![(None, None)], key_signatures: Vec::new(), warnings: Vec::new() })
+}
+
+/// Read a note such as `Bb3`, `F#4` or `C5:1` as a midi key along with how it is spelled and its pinned fingering
+fn read_note(token: &str) -> Option<(i16, Option<Spelling>, Option<usize>)>
+{
+    let (pitch, fingering) = match token.split_once(':')
+    {
+        Some((pitch, fingering)) => (pitch, Some(fingering.parse().ok()?)),
+        None => (token, None)
+    };
+    let mut chars = pitch.chars();
+    let letter = chars.next()?;
+    let mut alter: i16 = 0;
+    let mut octave = chars.as_str();
+    while let Some(c) = chars.next()
+    {
+        match c
+        {
+            'b' | '\u{266D}' => alter = alter.checked_sub(1)?,
+            '#' | '\u{266F}' => alter = alter.checked_add(1)?,
+            _ => break
+        }
+        octave = chars.as_str();
+    }
+    // Octaves are limited to those of midi keys, and notes with more than one flat or sharp aren't spelled
+    let octave = octave.parse::<i16>().ok().filter(|octave| (-1..=9).contains(octave))?;
+    let key = ((octave + 1) * 12 + Spelling::natural(letter)?).checked_add(alter)?;
+    let spelling = if (-1..=1).contains(&alter) { Spelling::with_letter(key, letter) } else { None };
+    Some((key, spelling, fingering))
+}
@@ -0,0 +1,141 @@
+use image::{Rgb, Rgba};
+use serde::Deserialize;
+
+/// A color written in cfg.ron as `(red, green, blue)`
+#[derive(Copy, Clone, Deserialize, PartialEq, Eq, Debug)]
+pub struct Color(pub u8, pub u8, pub u8);
+
+impl Color
+{
+    /// This color as an opaque pixel
+    pub fn rgba(self) -> Rgba<u8>
+    {
+        Rgba([self.0, self.1, self.2, 255])
+    }
+
+    /// This color as a pixel without transparency
+    pub fn rgb(self) -> Rgb<u8>
+    {
+        Rgb([self.0, self.1, self.2])
+    }
+
+    /// This color formatted for use in svg
+    pub fn hex(self) -> String
+    {
+        format!("#{:02x}{:02x}{:02x}", self.0, self.1, self.2)
+    }
+}
+
+impl From<Rgba<u8>> for Color
+{
+    /// The color of a pixel, ignoring its transparency
+    fn from(pixel: Rgba<u8>) -> Self
+    {
+        Color(pixel[0], pixel[1], pixel[2])
+    }
+}
+
+/// The colors and padding charts are drawn with. Any field left out of a custom theme in cfg.ron uses the default.
+#[derive(Copy, Clone, Deserialize, PartialEq, Debug)]
+#[serde(default)]
+pub struct Theme
+{
+    /// Behind and between charts, and behind headers
+    pub background: Color,
+    /// Inside each chart
+    pub chart_background: Color,
+    /// Keys which are pressed, rests and duration bars
+    pub filled: Color,
+    /// The outline of keys which aren't pressed
+    pub unfilled: Color,
+    /// The line between the left and right hand keys
+    pub separator: Color,
+    /// Headers, labels and bar lines
+    pub font: Color,
+    /// When set, every note name is drawn in this color instead of the color of its octave
+    #[serde(deserialize_with = "crate::layer::some")]
+    pub note_names: Option<Color>,
+    /// Blank space added around each chart, in pixels
+    pub padding: u32
+}
+
+impl Default for Theme
+{
+    fn default() -> Self
+    {
+        Theme
+        {
+            background: Color(0, 0, 0),
+            chart_background: Color(255, 255, 255),
+            filled: Color(0, 0, 0),
+            unfilled: Color(128, 128, 128),
+            separator: Color(128, 128, 128),
+            font: Color(255, 255, 255),
+            note_names: None,
+            padding: 0
+        }
+    }
+}
+
+/// The theme charts are drawn with, either built-in or custom
+#[derive(Copy, Clone, Deserialize, PartialEq, Default, Debug)]
+pub enum Themes
+{
+    /// Black keys on white charts, laid out on black
+    #[default]
+    Default,
+    /// Light keys on dark gray charts, which are easier on the eyes when projected
+    Dark,
+    /// Black and white only, with every note name in black
+    HighContrast,
+    /// Black keys on white charts laid out on white, which saves ink when printed
+    Print,
+    /// A theme given in full, such as `Custom((background: (255, 255, 255), padding: 2))`
+    Custom(Theme)
+}
+
+impl Themes
+{
+    /// The colors and padding of this theme
+    pub fn resolve(&self) -> Theme
+    {
+        match *self
+        {
+            Themes::Default => Theme::default(),
+            Themes::Dark => Theme
+            {
+                background: Color(18, 18, 18),
+                chart_background: Color(40, 40, 40),
+                filled: Color(235, 235, 235),
+                unfilled: Color(120, 120, 120),
+                separator: Color(120, 120, 120),
+                font: Color(235, 235, 235),
+                note_names: None,
+                padding: 0
+            },
+            Themes::HighContrast => Theme
+            {
+                background: Color(0, 0, 0),
+                chart_background: Color(255, 255, 255),
+                filled: Color(0, 0, 0),
+                unfilled: Color(0, 0, 0),
+                separator: Color(0, 0, 0),
+                font: Color(255, 255, 255),
+                note_names: Some(Color(0, 0, 0)),
+                padding: 2
+            },
+            Themes::Print => Theme
+            {
+                background: Color(255, 255, 255),
+                chart_background: Color(255, 255, 255),
+                filled: Color(0, 0, 0),
+                unfilled: Color(96, 96, 96),
+                separator: Color(96, 96, 96),
+                font: Color(0, 0, 0),
+                note_names: None,
+                padding: 2
+            },
+            Themes::Custom(theme) => theme
+        }
+    }
+}
@@ -1,138 +1,465 @@
-use crate::note::{NoteConst, Fingering};
-use image::{DynamicImage, GenericImage};
+use crate::{Config, Layout, Size, FingeringSelection, cost::CostModel, instrument::PERCUSSION_CHANNEL, note::{Note, Fingering, Spelling}, song::Metadata, theme::Theme};
+use crate::keys::{blit_scaled, gen_background, gen_rest_chart, gen_bar_line, with_duration, with_padding, font::{draw_text, text_width, LINE_HEIGHT}, image_data::{BAR_LINE_WIDTH, CHART_SIZE, DURATION_HEIGHT}};
+use image::{DynamicImage, RgbImage};
+use std::sync::Arc;
+
+/// Rests shorter than this fraction of a beat are not shown on charts
+const MIN_REST_FRACTION: u32 = 4;
+
+/// A single note in a track, along with its timing in midi ticks
+pub struct TrackNote
+{
+    pub note: Arc<Note>,
+    /// The tick this note starts on
+    pub start: u32,
+    /// The number of ticks this note is held
+    pub duration: u32,
+    /// The number of silent ticks before this note starts
+    pub rest: u32,
+    /// How the note's name is written, as the file says or spelled in its key signature. Otherwise the note's default spelling is used.
+    pub spelling: Option<Spelling>,
+    /// The index of the fingering this note must use, when the file says. Otherwise a fingering is selected.
+    pub fingering: Option<usize>
+}
+
+impl TrackNote
+{
+    /// The fingerings this note may be played with, which is only the pinned fingering if there is one
+    pub fn candidates(&self) -> &[Fingering]
+    {
+        match self.fingering
+        {
+            Some(fingering) => std::slice::from_ref(&self.note.fingerings[fingering]),
+            None => &self.note.fingerings
+        }
+    }
+}
 
 /// Struct used for tracks
-pub struct Track(pub Vec<NoteConst>);
+pub struct Track
+{
+    /// A name describing the track, unique within its song
+    pub name: String,
+    /// The instrument the track is played on, if the midi file says
+    pub instrument: Option<String>,
+    /// Information about the song the track belongs to
+    pub metadata: Arc<Metadata>,
+    /// The midi channels the track's notes are played on, counting from 0
+    pub channels: Vec<u8>,
+    pub notes: Vec<TrackNote>,
+    pub ticks_per_beat: u32,
+    /// The tick each measure starts on, the first measure always starts on tick 0
+    pub bars: Vec<u32>
+}
+
+/// A single cell of a chart, before it is drawn. Lengths are in beats.
+pub enum Cell<'a>
+{
+    Note(&'a Note, &'a Fingering, Option<Spelling>, f32),
+    Rest(f32),
+    BarLine
+}
 
 impl Track
 {
-    /// Generates an image for the entire track
-    pub fn track_image(&self, notes_per_row: usize, spacing: usize) -> DynamicImage
+    /// Generates an image for the entire track at the given size, with the title header above it if enabled. The
+    /// size of the image is worked out first so every chart is drawn straight onto it.
+    pub fn track_image(&self, config: &Config, size: Size) -> DynamicImage
     {
-        let mut track_image: DynamicImage = DynamicImage::new_rgb8(0, spacing as u32);
-        for row_image in self.row_images(notes_per_row, spacing)
+        let spacing = config.spacing as u32;
+        let theme = config.theme.resolve();
+        let header = self.header(config);
+        let rows = self.rows(config);
+        let (header_width, header_height) = header_size(&header, config.spacing);
+        let width = rows.iter().map(|cells| row_width(cells, config, &theme)).fold(header_width, u32::max);
+        let height = spacing + header_height + rows.iter().map(|cells| row_height(cells, config, &theme) + spacing).sum::<u32>();
+        let scale = size.scale(width);
+        let mut track_image = RgbImage::from_pixel(width * scale, height * scale, theme.background.rgb());
+        if !header.is_empty()
         {
-            let previous = track_image;
-            let new_width = std::cmp::max(previous.width(), row_image.width());
-            let new_height = previous.height() + row_image.height() + spacing as u32;
-            track_image = DynamicImage::new_rgb8(new_width, new_height);
-            track_image.copy_from(&previous, 0, 0).expect("Failed to copy track image");
-            track_image.copy_from(&row_image, 0, previous.height()).expect("Failed to generate track image");
+            let mut header_image = gen_background(header_width, header_height, &theme);
+            for (i, line) in header.iter().enumerate()
+            {
+                draw_text(&mut header_image, line, spacing, i as u32 * LINE_HEIGHT, theme.font.rgba());
+            }
+            blit_scaled(&mut track_image, &header_image, 0, spacing * scale, scale);
         }
-        track_image
+        let mut y = spacing + header_height;
+        for cells in &rows
+        {
+            draw_row(&mut track_image, cells, config, &theme, y, scale);
+            y += row_height(cells, config, &theme) + spacing;
+        }
+        DynamicImage::ImageRgb8(track_image)
     }
 
-    /// Generates images in rows
-    pub fn row_images(&self, notes_per_row: usize, spacing: usize) -> Vec<DynamicImage>
+    /// Generates images in rows, each at the given size
+    pub fn row_images(&self, config: &Config, size: Size) -> Vec<DynamicImage>
     {
-        let fingerings = self.select_fingerings();
-        (0..fingerings.len())
-            .take_while(|row| row * notes_per_row < self.notes().len())
-            .map(|row|
+        let theme = config.theme.resolve();
+        self.rows(config)
+            .iter()
+            .map(|cells|
             {
-                let mut row_image: DynamicImage = DynamicImage::new_rgb8(spacing as u32, 0);
-                for image in (0..notes_per_row)
-                    .take_while(|col| col + (row * notes_per_row) < self.notes().len())
-                    .map(|col| fingerings[(row * notes_per_row) + col])
+                let width = row_width(cells, config, &theme);
+                let scale = size.scale(width);
+                let mut row_image = RgbImage::from_pixel(width * scale, row_height(cells, config, &theme) * scale, theme.background.rgb());
+                draw_row(&mut row_image, cells, config, &theme, 0, scale);
+                DynamicImage::ImageRgb8(row_image)
+            })
+            .collect()
+    }
+
+    /// Generates cell images. Fingering images themselves are generated at load-time, rests and 
+    /// duration bars are added here if enabled.
+    pub fn cell_images(&self, config: &Config) -> Vec<DynamicImage>
+    {
+        let theme = config.theme.resolve();
+        self.cells(config).iter().map(|cell| cell.image(config, &theme)).collect()
+    }
+
+    /// Lays the track out in rows. Depending on the layout, rows either wrap after a fixed number of 
+    /// notes or only at bar lines.
+    pub fn rows(&self, config: &Config) -> Vec<Vec<Cell<'_>>>
+    {
+        match config.layout
+        {
+            Layout::Notes =>
+            {
+                let mut cells = self.cells(config).into_iter();
+                std::iter::from_fn(|| Some(cells.by_ref().take(config.notes_per_row).collect::<Vec<_>>()))
+                    .take_while(|row| !row.is_empty())
+                    .collect()
+            },
+            Layout::Measures =>
+            {
+                // Fit as many whole measures in a row as possible. Measures longer than a row get a row to themselves.
+                let mut rows = Vec::new();
+                let mut row = Vec::new();
+                let mut row_notes = 0;
+                for measure in self.measures(config)
                 {
-                    let previous = row_image;
-                    let new_width = previous.width() + image.width() + spacing as u32;
-                    let new_height = std::cmp::max(previous.height(), image.height());
-                    row_image = DynamicImage::new_rgb8(new_width, new_height);
-                    row_image.copy_from(&previous, 0, 0).expect("Failed to copy row image");
-                    row_image.copy_from(image, previous.width(), 0).expect("Failed to generate row image");
+                    if row_notes > 0 && row_notes + measure.len() > config.notes_per_row
+                    {
+                        rows.push(std::mem::take(&mut row));
+                        row_notes = 0;
+                    }
+                    if row_notes > 0
+                    {
+                        row.push(Cell::BarLine);
+                    }
+                    row_notes += measure.len();
+                    row.extend(measure);
                 }
-                row_image
-            }).collect()
+                if !row.is_empty()
+                {
+                    rows.push(row);
+                }
+                rows
+            }
+        }
     }
 
-    /// Returns an iterator into cell images. The images themselves are 
-    /// generated at load-time, so this method is low-cost.
-    pub fn cell_images(&self) -> Vec<&DynamicImage>
+    /// Lists every cell in the track, with rests included if enabled
+    pub fn cells(&self, config: &Config) -> Vec<Cell<'_>>
     {
-        self.select_fingerings()
+        let mut cells = Vec::with_capacity(self.notes().len());
+        for (note, fingering) in self.notes().iter().zip(self.select_fingerings(config.fingering_selection, &config.costs))
+        {
+            if config.show_rests
+            {
+                cells.extend(self.rest(note.rest));
+            }
+            cells.push(Cell::Note(&note.note, fingering, note.spelling, self.beats(note.duration)));
+        }
+        cells
+    }
+
+    /// Lists every cell in the track grouped into measures. Rests which cross a bar line are split so 
+    /// that each part ends up in the measure it belongs to. Empty measures are left out.
+    pub fn measures(&self, config: &Config) -> Vec<Vec<Cell<'_>>>
+    {
+        let mut measures: Vec<Vec<Cell>> = self.bars.iter().map(|_| Vec::new()).collect();
+        for (note, fingering) in self.notes().iter().zip(self.select_fingerings(config.fingering_selection, &config.costs))
+        {
+            if config.show_rests
+            {
+                let rest_from = note.start - note.rest;
+                let mut rest_start = rest_from;
+                for &rest_end in self.bars
+                    .iter()
+                    .filter(|&&bar| bar > rest_from && bar < note.start)
+                    .chain(std::iter::once(&note.start))
+                {
+                    measures[self.measure(rest_start)].extend(self.rest(rest_end - rest_start));
+                    rest_start = rest_end;
+                }
+            }
+            measures[self.measure(note.start)].push(Cell::Note(&note.note, fingering, note.spelling, self.beats(note.duration)));
+        }
+        measures.retain(|measure| !measure.is_empty());
+        measures
+    }
+
+    /// Lines of text drawn above the entire track: the song title and track name, then the copyright notice. 
+    /// The title is left out when the track is named after it. Empty unless the config enables titles.
+    pub fn header(&self, config: &Config) -> Vec<String>
+    {
+        if !config.show_title
+        {
+            return Vec::new();
+        }
+        let mut lines = vec![match &self.metadata.title
+        {
+            Some(title) if *title != self.name => format!("{} - {}", title, self.name),
+            _ => self.name.clone()
+        }];
+        lines.extend(self.metadata.copyright.clone());
+        lines
+    }
+
+    /// The name used for files generated from this track
+    pub fn file_name(&self) -> String
+    {
+        file_name(&self.name)
+    }
+
+    /// Whether every note in the track is played on the percussion channel
+    pub fn is_percussion(&self) -> bool
+    {
+        !self.channels.is_empty() && self.channels.iter().all(|&channel| channel == PERCUSSION_CHANNEL)
     }
 
     /// Access notes vector
-    pub fn notes(&self) -> &Vec<NoteConst>
+    pub fn notes(&self) -> &Vec<TrackNote>
     {
-        &self.0
+        &self.notes
     }
 
     /// Access notes vector
-    pub fn notes_mut(&mut self) -> &mut Vec<NoteConst>
+    pub fn notes_mut(&mut self) -> &mut Vec<TrackNote>
+    {
+        &mut self.notes
+    }
+
+    /// A rest cell, if the rest is long enough to be shown
+    fn rest(&self, ticks: u32) -> Option<Cell<'static>>
     {
-        &mut self.0
+        if ticks < self.ticks_per_beat / MIN_REST_FRACTION
+        {
+            None
+        }
+        else
+        {
+            Some(Cell::Rest(self.beats(ticks)))
+        }
+    }
+
+    /// Determine which measure a tick falls in
+    fn measure(&self, tick: u32) -> usize
+    {
+        self.bars.partition_point(|&bar| bar <= tick) - 1
+    }
+
+    /// Convert a number of ticks to a number of beats
+    fn beats(&self, ticks: u32) -> f32
+    {
+        ticks as f32 / self.ticks_per_beat as f32
     }
 
     /// Go through candidates and determine which fingerings to use
-    fn select_fingerings(&self) -> Vec<&DynamicImage>
+    pub fn select_fingerings(&self, selection: FingeringSelection, costs: &CostModel) -> Vec<&Fingering>
+    {
+        match selection
+        {
+            FingeringSelection::Greedy => self.select_greedy(costs),
+            FingeringSelection::Optimal => self.select_optimal(costs)
+        }
+    }
+
+    /// Pick fingerings that minimize the total transition cost over the entire track. For each note, the 
+    /// cheapest path ending on each of its fingerings is kept, then the cheapest path overall is traced back.
+    fn select_optimal(&self, costs: &CostModel) -> Vec<&Fingering>
+    {
+        let notes = self.notes();
+        let first = match notes.first()
+        {
+            Some(first) => first,
+            None => return Vec::new()
+        };
+
+        // Lowest cost of a path ending on each fingering of the current note, and for each later note, 
+        // which fingering of the previous note that path came from
+        let mut path_costs: Vec<f32> = first.candidates().iter().map(|fingering| -costs.preference(fingering)).collect();
+        let mut paths: Vec<Vec<usize>> = Vec::with_capacity(notes.len());
+        for pair in notes.windows(2)
+        {
+            let (previous, current) = (pair[0].candidates(), pair[1].candidates());
+            let (next_costs, path) = current
+                .iter()
+                .map(|to| previous
+                    .iter()
+                    .enumerate()
+                    .map(|(i, from)| (path_costs[i] + costs.transition(from, to), i))
+                    .min_by(|a, b| a.0.total_cmp(&b.0))
+                    .expect("Note has no fingerings"))
+                .unzip();
+            path_costs = next_costs;
+            paths.push(path);
+        }
+
+        // Trace the cheapest path backwards. Earlier fingerings are favored in the case of equivalence.
+        let mut choice = (0..path_costs.len())
+            .min_by(|&a, &b| path_costs[a].total_cmp(&path_costs[b]))
+            .expect("Note has no fingerings");
+        let mut choices = vec![choice];
+        for path in paths.iter().rev()
+        {
+            choice = path[choice];
+            choices.push(choice);
+        }
+        notes
+            .iter()
+            .zip(choices.into_iter().rev())
+            .map(|(note, choice)| &note.candidates()[choice])
+            .collect()
+    }
+
+    /// Pick each fingering by comparing it against the previous choice only. The first note is compared 
+    /// against the candidates for the note after it instead.
+    fn select_greedy(&self, costs: &CostModel) -> Vec<&Fingering>
     {
+        // Pick the cheapest of a list of candidates. Earlier candidates are favored in the case of equivalence.
+        fn cheapest(candidates: &[Fingering], cost: impl Fn(&Fingering) -> f32) -> &Fingering
+        {
+            candidates
+                .iter()
+                .map(|candidate| (cost(candidate), candidate))
+                .min_by(|a, b| a.0.total_cmp(&b.0))
+                .expect("Note has no fingerings")
+                .1
+        }
+
         let mut previous_choice: Option<&Fingering> = None;
         self.notes()
             .iter()
             .enumerate()
-            .map(|(i, notes)| (i, &notes.fingerings))
-            .filter_map(|(i, fingerings)| 
+            .map(|(i, note)|
             {
-                // Determine how alike a note is from a list of candidates
-                fn intersection<'a>(note: &Fingering, candidates: impl Iterator<Item = &'a Fingering>) -> i32
+                let fingerings = note.candidates();
+                let choice = match (previous_choice, self.notes().get(i + 1))
                 {
-                    candidates
-                        .map(|candidate|candidate.keys)
-                        .map(|keys|note.keys.intersection(keys).len())
-                        .max()
-                        .unwrap() as i32
-                }
+                    (Some(previous), _) => cheapest(fingerings, |fingering| costs.transition(previous, fingering)),
+                    (None, Some(next)) => cheapest(fingerings, |fingering| 
+                    {
+                        let closest = cheapest(next.candidates(), |sibling| costs.transition(fingering, sibling));
+                        costs.transition(fingering, closest) - costs.preference(fingering)
+                    }),
+                    (None, None) => cheapest(fingerings, |fingering| -costs.preference(fingering))
+                };
+                previous_choice = Some(choice);
+                choice
+            })
+            .collect()
+    }
+}
 
-                // Determine how different a note is from a list of candidates
-                fn difference<'a>(note: &Fingering, candidates: impl Iterator<Item = &'a Fingering>) -> i32
-                {
-                    candidates
-                        .map(|candidate|candidate.keys)
-                        .map(|keys|note.keys.symmetrical_difference(keys).len())
-                        .min()
-                        .unwrap() as i32
-                }
+impl Cell<'_>
+{
+    /// The width of this cell's image in pixels
+    pub fn width(&self, theme: &Theme) -> u32
+    {
+        match self
+        {
+            Cell::BarLine => BAR_LINE_WIDTH as u32,
+            _ => CHART_SIZE.0 as u32 + theme.padding * 2
+        }
+    }
 
-                // Take two candidates notes and one or more others to compare against, determine which note is closer.
-                // Note 1 is favored slightly in that it will be used in the case of equivalence
-                fn compare_notes<'a>(note_1: &'a Fingering, note_2: &'a Fingering, siblings: &Vec<&Fingering>) -> &'a Fingering
-                {
-                    let note_1_intersection = intersection(note_1, siblings.iter().map(|f|*f));
-                    let note_1_diff = difference(note_1, siblings.iter().map(|f|*f));
-                    let note_2_intersection = intersection(note_2, siblings.iter().map(|f|*f));
-                    let note_2_diff = difference(note_2, siblings.iter().map(|f|*f));
-                    if note_2_intersection - note_2_diff > note_1_intersection - note_1_diff
-                    {
-                        note_2
-                    }
-                    else
-                    {
-                        note_1
-                    }
-                }
+    /// Draws this cell. Every cell is the same height, so bar lines line up with the charts around them.
+    pub fn image(&self, config: &Config, theme: &Theme) -> DynamicImage
+    {
+        let chart = match *self
+        {
+            Cell::Note(note, fingering, spelling, _) => fingering.spelled_image(note.byte, spelling, config, theme),
+            Cell::Rest(_) => gen_rest_chart(theme),
+            Cell::BarLine => return gen_bar_line(cell_height(config, theme), theme)
+        };
+        match *self
+        {
+            Cell::Note(_, _, _, beats) | Cell::Rest(beats) if config.show_durations => with_padding(&with_duration(&chart, beats, theme), theme),
+            _ => with_padding(&chart, theme)
+        }
+    }
+}
 
-                // Determine what fingering to actually use
-                previous_choice = Some(if fingerings.len() == 1
-                {
-                    &fingerings[0]
-                }
-                else if i == 0
-                {
-                    let siblings = self.notes()[i + 1].fingerings.iter().collect();
-                    fingerings.iter().fold(&fingerings[0], |note_1, note_2| &compare_notes(note_1, note_2, &siblings))
-                }
-                else
-                {
-                    let sibling = vec!(previous_choice.unwrap());
-                    fingerings.iter().fold(&fingerings[0], |note_1, note_2| &compare_notes(note_1, note_2, &sibling))
-                });
+/// The size of a header in pixels, including the spacing around it. Headers without any lines take up no space.
+pub fn header_size(header: &[String], spacing: usize) -> (u32, u32)
+{
+    if header.is_empty()
+    {
+        return (0, 0);
+    }
+    let width = header.iter().map(|line| text_width(line)).max().unwrap_or(0) + 2 * spacing as u32;
+    (width, header.len() as u32 * LINE_HEIGHT + spacing as u32)
+}
 
-                // Return the choice made above (it's actually the "current_choice" at this point)
-                previous_choice
-            })
-            .map(|fingering| &fingering.image)
-            .collect()
+/// Turn a name into one that can be used for files. Anything other than letters and numbers becomes an underscore,
+/// and the result is lowercase.
+pub fn file_name(name: &str) -> String
+{
+    let mut file_name = String::with_capacity(name.len());
+    for c in name.chars()
+    {
+        if c.is_alphanumeric()
+        {
+            file_name.extend(c.to_lowercase());
+        }
+        else if !file_name.is_empty() && !file_name.ends_with('_')
+        {
+            file_name.push('_');
+        }
+    }
+    while file_name.ends_with('_')
+    {
+        file_name.pop();
+    }
+    if file_name.is_empty()
+    {
+        file_name.push_str("track");
     }
+    file_name
+}
+
+/// The width of a row of cells in pixels, including the spacing around them
+pub fn row_width(cells: &[Cell<'_>], config: &Config, theme: &Theme) -> u32
+{
+    let spacing = config.spacing as u32;
+    spacing + cells.iter().map(|cell| cell.width(theme) + spacing).sum::<u32>()
+}
+
+/// The height of a row of cells in pixels
+fn row_height(cells: &[Cell<'_>], config: &Config, theme: &Theme) -> u32
+{
+    if cells.is_empty() { 0 } else { cell_height(config, theme) }
+}
+
+/// Draws a row of cells onto an image which has room for them, starting from the given unscaled height
+fn draw_row(image: &mut RgbImage, cells: &[Cell<'_>], config: &Config, theme: &Theme, y: u32, scale: u32)
+{
+    let spacing = config.spacing as u32;
+    let mut x = spacing;
+    for cell in cells
+    {
+        let cell_image = cell.image(config, theme);
+        blit_scaled(image, &cell_image, x * scale, y * scale, scale);
+        x += cell_image.width() + spacing;
+    }
+}
+
+/// The height of a cell in pixels, including the theme's padding
+pub fn cell_height(config: &Config, theme: &Theme) -> u32
+{
+    let duration_height = if config.show_durations { DURATION_HEIGHT as u32 } else { 0 };
+    CHART_SIZE.1 as u32 + duration_height + theme.padding * 2
 }